@@ -21,7 +21,7 @@ mkdir "$vim_config"
 # for vim-plug in run_profile.vim.
 echo '
 ---
-plugin_dir: ~/.vim/pack/strand/start
+pack_dir: ~/.vim/pack/strand
 
 plugins:
   - Git: PeterRincker/vim-searchlight
@@ -0,0 +1,245 @@
+//! Unpacking the kinds of archive plugins are distributed as.
+
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    io::{self, Read},
+    path::{Component, Path, PathBuf},
+};
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    #[serde(rename = "tar.gz")]
+    TarGz,
+    #[serde(rename = "tar.xz")]
+    TarXz,
+    #[serde(rename = "tar.bz2")]
+    TarBz2,
+    #[serde(rename = "tar.zst")]
+    TarZst,
+    #[serde(rename = "tar")]
+    Tar,
+    #[serde(rename = "zip")]
+    Zip,
+}
+
+impl Format {
+    /// Works out an archive’s format from the magic bytes at its start, or failing that from the
+    /// Content-Type it was served with. Only tarballs from before POSIX lack magic bytes.
+    pub fn detect(bytes: &[u8], content_type: Option<&str>) -> Option<Self> {
+        let format = if bytes.starts_with(b"\x1f\x8b") {
+            Format::TarGz
+        } else if bytes.starts_with(b"\xfd7zXZ\0") {
+            Format::TarXz
+        } else if bytes.starts_with(b"BZh") {
+            Format::TarBz2
+        } else if bytes.starts_with(b"\x28\xb5\x2f\xfd") {
+            Format::TarZst
+        } else if bytes.starts_with(b"PK\x03\x04") || bytes.starts_with(b"PK\x05\x06") {
+            Format::Zip
+        } else if bytes.get(257..262) == Some(b"ustar") {
+            Format::Tar
+        } else {
+            return content_type.and_then(Self::from_content_type);
+        };
+
+        Some(format)
+    }
+
+    fn from_content_type(content_type: &str) -> Option<Self> {
+        let mime_type = content_type.split(';').next().unwrap_or_default().trim();
+
+        match mime_type.to_ascii_lowercase().as_str() {
+            "application/gzip" | "application/x-gzip" | "application/x-gtar" => Some(Format::TarGz),
+            "application/x-xz" => Some(Format::TarXz),
+            "application/x-bzip2" => Some(Format::TarBz2),
+            "application/zstd" => Some(Format::TarZst),
+            "application/x-tar" => Some(Format::Tar),
+            "application/zip" | "application/x-zip-compressed" => Some(Format::Zip),
+            _ => None,
+        }
+    }
+}
+
+// Every format goes through this check, so that no archive entry can write outside the directory it
+// is being unpacked into.
+fn entry_path(path: &Path) -> Result<PathBuf> {
+    let mut entry_path = PathBuf::new();
+
+    for component in path.components() {
+        match component {
+            Component::Normal(part) => entry_path.push(part),
+            Component::CurDir => {}
+            _ => bail!(
+                "archive entry ‘{}’ points outside the plugin’s directory",
+                path.display()
+            ),
+        }
+    }
+
+    Ok(entry_path)
+}
+
+fn unpack_tar(reader: impl Read, dest: &Path) -> Result<()> {
+    let mut archive = tar::Archive::new(reader);
+
+    fs::create_dir_all(dest)?;
+
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        entry_path(&entry.path()?)?;
+
+        // This also refuses to follow links the archive itself created out of the directory.
+        entry.unpack_in(dest)?;
+    }
+
+    Ok(())
+}
+
+fn unpack_zip(bytes: &[u8], dest: &Path) -> Result<()> {
+    let mut archive = zip::ZipArchive::new(io::Cursor::new(bytes))?;
+
+    fs::create_dir_all(dest)?;
+
+    for i in 0..archive.len() {
+        let mut file = archive.by_index(i)?;
+        let path = dest.join(entry_path(Path::new(file.name()))?);
+
+        if file.is_dir() {
+            fs::create_dir_all(&path)?;
+            continue;
+        }
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        io::copy(&mut file, &mut fs::File::create(&path)?)?;
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+
+            // Only the permission bits are kept, so that e.g. build scripts stay executable.
+            if let Some(mode) = file.unix_mode() {
+                fs::set_permissions(&path, fs::Permissions::from_mode(mode & 0o777))?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Unpacks an archive of the given format into `dest`, which is created if need be.
+pub fn unpack(bytes: &[u8], format: Format, dest: &Path) -> Result<()> {
+    match format {
+        Format::TarGz => unpack_tar(flate2::read::GzDecoder::new(bytes), dest),
+        Format::TarXz => unpack_tar(xz2::read::XzDecoder::new(bytes), dest),
+        Format::TarBz2 => unpack_tar(bzip2::read::BzDecoder::new(bytes), dest),
+        Format::TarZst => unpack_tar(zstd::stream::read::Decoder::new(bytes)?, dest),
+        Format::Tar => unpack_tar(bytes, dest),
+        Format::Zip => unpack_zip(bytes, dest),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn tar(files: &[(&str, &str)]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+
+        for (path, contents) in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(contents.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder
+                .append_data(&mut header, path, contents.as_bytes())
+                .unwrap();
+        }
+
+        builder.into_inner().unwrap()
+    }
+
+    fn zip(files: &[(&str, &str)]) -> Vec<u8> {
+        let mut writer = zip::ZipWriter::new(io::Cursor::new(Vec::new()));
+
+        for (path, contents) in files {
+            writer
+                .start_file(*path, zip::write::FileOptions::default())
+                .unwrap();
+            writer.write_all(contents.as_bytes()).unwrap();
+        }
+
+        writer.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn test_unpack() {
+        use flate2::{write::GzEncoder, Compression};
+
+        let dir = std::env::temp_dir().join("strand-test-archive-unpack");
+        let _ = fs::remove_dir_all(&dir);
+
+        let files = [("vim-qlist/plugin/qlist.vim", "\" qlist")];
+        let tar = tar(&files);
+
+        let mut gz = GzEncoder::new(Vec::new(), Compression::default());
+        gz.write_all(&tar).unwrap();
+
+        let mut xz = xz2::write::XzEncoder::new(Vec::new(), 6);
+        xz.write_all(&tar).unwrap();
+
+        let mut bz2 = bzip2::write::BzEncoder::new(Vec::new(), bzip2::Compression::Default);
+        bz2.write_all(&tar).unwrap();
+
+        let archives = [
+            (gz.finish().unwrap(), Format::TarGz),
+            (xz.finish().unwrap(), Format::TarXz),
+            (bz2.finish().unwrap(), Format::TarBz2),
+            (zstd::encode_all(&tar[..], 0).unwrap(), Format::TarZst),
+            (tar.clone(), Format::Tar),
+            (zip(&files), Format::Zip),
+        ];
+
+        for (i, (archive, format)) in archives.iter().enumerate() {
+            assert_eq!(Format::detect(archive, None), Some(*format));
+
+            let dest = dir.join(i.to_string());
+            unpack(archive, *format, &dest).unwrap();
+            assert_eq!(
+                fs::read_to_string(dest.join("vim-qlist/plugin/qlist.vim")).unwrap(),
+                "\" qlist"
+            );
+        }
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_detect_from_content_type() {
+        assert_eq!(
+            Format::detect(b"", Some("application/zip; charset=binary")),
+            Some(Format::Zip)
+        );
+        assert_eq!(Format::detect(b"<html>", Some("text/html")), None);
+        assert_eq!(Format::detect(b"<html>", None), None);
+    }
+
+    #[test]
+    fn test_unpack_outside_dest() {
+        let dir = std::env::temp_dir().join("strand-test-archive-outside");
+        let _ = fs::remove_dir_all(&dir);
+
+        let archive = zip(&[("../evil.vim", "")]);
+        let error = unpack(&archive, Format::Zip, &dir.join("plugin")).unwrap_err();
+
+        assert!(error.to_string().contains("points outside"));
+        assert!(!dir.join("evil.vim").exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,133 @@
+use anyhow::{bail, Context, Result};
+use async_std::task;
+use std::{
+    io::Read,
+    path::Path,
+    process::{Command, Output, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+#[cfg(not(windows))]
+fn shell(command: &str) -> Command {
+    let mut shell = Command::new("sh");
+    shell.arg("-c").arg(command);
+    shell
+}
+
+#[cfg(windows)]
+fn shell(command: &str) -> Command {
+    let mut shell = Command::new("cmd");
+    shell.arg("/C").arg(command);
+    shell
+}
+
+// Pipes have to be drained while the command runs, or it blocks once their buffers fill up.
+fn read_in_background(pipe: Option<impl Read + Send + 'static>) -> thread::JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut output = Vec::new();
+
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.read_to_end(&mut output);
+        }
+
+        output
+    })
+}
+
+/// Runs a command to completion without holding up other tasks, killing it if it takes longer than
+/// `timeout`. Its output is captured rather than mixed in with ours. `name` is how the command is
+/// referred to in errors.
+pub async fn output(command: &mut Command, name: &str, timeout: Duration) -> Result<Output> {
+    let mut child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("failed to run ‘{}’", name))?;
+
+    let stdout = read_in_background(child.stdout.take());
+    let stderr = read_in_background(child.stderr.take());
+
+    let start = Instant::now();
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+
+        if start.elapsed() > timeout {
+            let _ = child.kill();
+            let _ = child.wait();
+            bail!(
+                "‘{}’ was killed after running for longer than {} seconds",
+                name,
+                timeout.as_secs()
+            );
+        }
+
+        task::sleep(Duration::from_millis(50)).await;
+    };
+
+    Ok(Output {
+        status,
+        stdout: stdout.join().unwrap_or_default(),
+        stderr: stderr.join().unwrap_or_default(),
+    })
+}
+
+/// Runs a plugin’s build command through the shell inside the given directory, killing it if it
+/// takes longer than `timeout`. The command’s output is only shown if it fails.
+pub async fn run(command: &str, dir: &Path, timeout: Duration) -> Result<()> {
+    let output = output(shell(command).current_dir(dir), command, timeout).await?;
+
+    if output.status.success() {
+        return Ok(());
+    }
+
+    let mut message = format!("‘{}’ failed with {}", command, output.status);
+
+    for (name, output) in [("stdout", output.stdout), ("stderr", output.stderr)] {
+        let output = String::from_utf8_lossy(&output);
+
+        if !output.trim().is_empty() {
+            message.push_str(&format!("\n{}:\n{}", name, output.trim_end()));
+        }
+    }
+
+    bail!(message)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[async_std::test]
+    async fn test_run() {
+        let dir = std::env::temp_dir().join("strand-test-build-run");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let timeout = Duration::from_secs(10);
+
+        run("echo built > output", &dir, timeout).await.unwrap();
+        assert_eq!(
+            std::fs::read_to_string(dir.join("output")).unwrap(),
+            "built\n"
+        );
+
+        let error = run("echo oops >&2; exit 3", &dir, timeout)
+            .await
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("exit status: 3"));
+        assert!(error.contains("stderr:\noops"));
+
+        let error = run("sleep 5", &dir, Duration::from_millis(100))
+            .await
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("killed"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
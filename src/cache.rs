@@ -0,0 +1,83 @@
+//! A cache of downloaded archives, so that plugins pinned to a version never have to be downloaded
+//! twice and can be installed without a network connection.
+
+use crate::sha256_hex;
+use anyhow::Result;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+/// Why a plugin could not be installed in offline mode.
+#[derive(Error, Debug)]
+pub enum OfflineError {
+    #[error("not in the download cache")]
+    NotCached,
+    #[error("no commit recorded in the lockfile or state file to install")]
+    Unresolved,
+}
+
+/// Archives are stored under the hash of a key that always stands for the same archive, such as the
+/// URL of a Git repo at a particular commit, or the hash an archive is expected to have. Only
+/// archives that have been checked against what their key promises should be put in the cache.
+pub struct Cache {
+    dir: PathBuf,
+}
+
+impl Cache {
+    pub fn new(dir: &Path) -> Self {
+        Self {
+            dir: dir.join("archives"),
+        }
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.dir.join(sha256_hex(key.as_bytes()))
+    }
+
+    pub async fn get(&self, key: &str) -> Option<Vec<u8>> {
+        async_std::fs::read(self.path(key)).await.ok()
+    }
+
+    pub async fn put(&self, key: &str, archive: &[u8]) -> Result<()> {
+        use async_std::fs;
+
+        fs::create_dir_all(&self.dir).await?;
+
+        // Writing to a temporary file first means that an interrupted write can never leave a
+        // truncated archive behind for the next run to pick up.
+        let path = self.path(key);
+        let tmp_path = path.with_extension("tmp");
+        fs::write(&tmp_path, archive).await?;
+        fs::rename(&tmp_path, &path).await?;
+
+        Ok(())
+    }
+
+    pub async fn remove(&self, key: &str) -> Result<()> {
+        async_std::fs::remove_file(self.path(key)).await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[async_std::test]
+    async fn test_cache() {
+        let dir = std::env::temp_dir().join("strand-test-cache");
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let cache = Cache::new(&dir);
+        let url = "https://codeload.github.com/tpope/vim-surround/tar.gz/4a97465";
+
+        assert_eq!(cache.get(url).await, None);
+        cache.put(url, b"archive").await.unwrap();
+        assert_eq!(cache.get(url).await.as_deref(), Some(&b"archive"[..]));
+        assert_eq!(cache.get("https://example.com/other.tar.gz").await, None);
+        cache.remove(url).await.unwrap();
+        assert_eq!(cache.get(url).await, None);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,283 @@
+//! Listing the commits between two versions of a Git plugin, using its host’s API since strand
+//! keeps no Git history of its own.
+
+use crate::{download, limit, GitProvider, GitRepo, InstallOptions, Plugin};
+use anyhow::{bail, Result};
+use async_std::task;
+use serde::{Deserialize, Serialize};
+
+/// A plugin that is moving from one commit to another.
+pub struct Update {
+    pub plugin: Plugin,
+    pub from: String,
+    pub to: String,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct Change {
+    pub commit: String,
+    /// The first line of the commit message.
+    pub subject: String,
+}
+
+/// The commits a plugin gained between two versions, newest first. Hosts only return so many
+/// commits at once, so very large updates may be cut short.
+#[derive(Serialize)]
+pub struct PluginChanges {
+    pub id: String,
+    pub from: String,
+    pub to: String,
+    pub commits: Vec<Change>,
+    pub error: Option<String>,
+}
+
+// GitHub, Gitea and GitLab all wrap the commits in an object, but describe them differently.
+#[derive(Deserialize)]
+struct Compare<C> {
+    commits: Vec<C>,
+}
+
+#[derive(Deserialize)]
+struct GitHubCommit {
+    sha: String,
+    commit: GitHubCommitDetails,
+}
+
+#[derive(Deserialize)]
+struct GitHubCommitDetails {
+    message: String,
+}
+
+#[derive(Deserialize)]
+struct GitLabCommit {
+    id: String,
+    message: String,
+}
+
+// Bitbucket Server calls the hash ‘id’ where bitbucket.org calls it ‘hash’.
+#[derive(Deserialize)]
+struct BitbucketPage {
+    values: Vec<BitbucketCommit>,
+}
+
+#[derive(Deserialize)]
+struct BitbucketCommit {
+    #[serde(alias = "id")]
+    hash: String,
+    message: String,
+}
+
+fn change(commit: String, message: &str) -> Change {
+    Change {
+        commit,
+        subject: message.lines().next().unwrap_or_default().into(),
+    }
+}
+
+fn compare_url(repo: &GitRepo, from: &str, to: &str) -> Result<String> {
+    let base = repo.base_url();
+    let (user, name) = (&repo.user, &repo.repo);
+
+    Ok(match repo.provider {
+        GitProvider::GitHub if repo.host.is_none() => format!(
+            "https://api.github.com/repos/{}/{}/compare/{}...{}",
+            user, name, from, to
+        ),
+        GitProvider::GitHub => format!(
+            "{}/api/v3/repos/{}/{}/compare/{}...{}",
+            base, user, name, from, to
+        ),
+        GitProvider::GitLab => format!(
+            "{}/api/v4/projects/{}%2F{}/repository/compare?from={}&to={}",
+            base, user, name, from, to
+        ),
+        GitProvider::Bitbucket if repo.host.is_none() => format!(
+            "https://api.bitbucket.org/2.0/repositories/{}/{}/commits/{}?exclude={}",
+            user, name, to, from
+        ),
+        GitProvider::Bitbucket => format!(
+            "{}/rest/api/latest/projects/{}/repos/{}/commits?since={}&until={}",
+            base, user, name, from, to
+        ),
+        GitProvider::Gitea | GitProvider::Forgejo | GitProvider::Codeberg => format!(
+            "{}/api/v1/repos/{}/{}/compare/{}...{}",
+            base, user, name, from, to
+        ),
+        GitProvider::Sourcehut => bail!("sourcehut has no API for comparing commits"),
+    })
+}
+
+// GitHub and GitLab list commits oldest first, and everyone else newest first.
+fn parse_changes(provider: &GitProvider, body: &[u8]) -> Result<Vec<Change>> {
+    let changes = match provider {
+        GitProvider::GitHub => {
+            let compare: Compare<GitHubCommit> = serde_json::from_slice(body)?;
+            let commits = compare.commits.into_iter().rev();
+            commits.map(|c| change(c.sha, &c.commit.message)).collect()
+        }
+        GitProvider::GitLab => {
+            let compare: Compare<GitLabCommit> = serde_json::from_slice(body)?;
+            let commits = compare.commits.into_iter().rev();
+            commits.map(|c| change(c.id, &c.message)).collect()
+        }
+        GitProvider::Bitbucket => {
+            let page: BitbucketPage = serde_json::from_slice(body)?;
+            let commits = page.values.into_iter();
+            commits.map(|c| change(c.hash, &c.message)).collect()
+        }
+        _ => {
+            let compare: Compare<GitHubCommit> = serde_json::from_slice(body)?;
+            let commits = compare.commits.into_iter();
+            commits.map(|c| change(c.sha, &c.commit.message)).collect()
+        }
+    };
+
+    Ok(changes)
+}
+
+/// Asks the plugin’s host which commits lead from one commit to another.
+async fn plugin_changes(
+    plugin: &Plugin,
+    from: &str,
+    to: &str,
+    options: &InstallOptions,
+) -> Result<Vec<Change>> {
+    let repo = match plugin {
+        Plugin::Git(repo) => repo,
+        _ => bail!("changes can only be listed for plugins on a Git host with an API"),
+    };
+
+    let url = compare_url(repo, from, to)?;
+    let body = download::get(&url, &options.download, &|_, _| ())
+        .await?
+        .body;
+
+    parse_changes(&repo.provider, &body)
+}
+
+/// Lists the changes in each update, in the order given.
+pub async fn changes(updates: Vec<Update>, options: &InstallOptions) -> Vec<PluginChanges> {
+    let limiter = limit::Limiter::new(options.jobs, options.jobs_per_host);
+
+    let tasks: Vec<_> = updates
+        .into_iter()
+        .map(|update| {
+            let options = options.clone();
+            let limiter = limiter.clone();
+
+            task::spawn(async move {
+                let _permit = limiter.acquire(&update.plugin.host()).await;
+                let result =
+                    plugin_changes(&update.plugin, &update.from, &update.to, &options).await;
+
+                let (commits, error) = match result {
+                    Ok(commits) => (commits, None),
+                    Err(e) => (Vec::new(), Some(format!("{:#}", e))),
+                };
+
+                PluginChanges {
+                    id: update.plugin.id(),
+                    from: update.from,
+                    to: update.to,
+                    commits,
+                    error,
+                }
+            })
+        })
+        .collect();
+
+    let mut changes = Vec::with_capacity(tasks.len());
+
+    for task in tasks {
+        changes.push(task.await);
+    }
+
+    changes
+}
+
+/// Prints each plugin’s commits like a short `git log`.
+pub fn print_changes(changes: &[PluginChanges]) {
+    for (i, plugin) in changes.iter().enumerate() {
+        if i > 0 {
+            println!();
+        }
+
+        println!("{} ({:.7}..{:.7})", plugin.id, plugin.from, plugin.to);
+
+        if let Some(error) = &plugin.error {
+            println!("  could not list changes: {}", error);
+        }
+
+        for change in &plugin.commits {
+            println!("  {:.7} {}", change.commit, change.subject);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test_server, DownloadOptions, ProgressStyle};
+    use std::time::Duration;
+
+    #[async_std::test]
+    async fn test_changes() {
+        let server = test_server::serve(|path| match path {
+            "/api/v1/repos/tpope/vim-surround/compare/aaaaaaa...ccccccc" => {
+                test_server::Response::ok(
+                    r#"{"commits": [
+                        {"sha": "ccccccc", "commit": {"message": "Fix the fix\n\nOops"}},
+                        {"sha": "bbbbbbb", "commit": {"message": "Fix a bug"}}
+                    ]}"#,
+                )
+            }
+            "/api/v4/projects/tpope%2Fvim-surround/repository/compare?from=aaaaaaa&to=ccccccc" => {
+                test_server::Response::ok(
+                    r#"{"commits": [
+                        {"id": "bbbbbbb", "message": "Fix a bug\n"},
+                        {"id": "ccccccc", "message": "Fix the fix\n\nOops\n"}
+                    ]}"#,
+                )
+            }
+            _ => test_server::Response::not_found(),
+        });
+
+        let update = |id: String| Update {
+            plugin: id.parse().unwrap(),
+            from: "aaaaaaa".into(),
+            to: "ccccccc".into(),
+        };
+
+        let options = InstallOptions {
+            run_timeout: Duration::from_secs(10),
+            cache_dir: std::env::temp_dir(),
+            offline: false,
+            fail_fast: false,
+            jobs: 8,
+            jobs_per_host: 4,
+            progress: ProgressStyle::Silent,
+            download: DownloadOptions::default(),
+        };
+
+        let updates = vec![
+            update(format!("gitea({})@tpope/vim-surround", server)),
+            update(format!("gitlab({})@tpope/vim-surround", server)),
+            update("sourcehut@~tpope/vim-surround".into()),
+        ];
+        let changes = changes(updates, &options).await;
+
+        let expected = [
+            Change {
+                commit: "ccccccc".into(),
+                subject: "Fix the fix".into(),
+            },
+            Change {
+                commit: "bbbbbbb".into(),
+                subject: "Fix a bug".into(),
+            },
+        ];
+        assert_eq!(changes[0].commits, expected);
+        assert_eq!(changes[1].commits, expected);
+        assert!(changes[2].error.as_ref().unwrap().contains("sourcehut"));
+    }
+}
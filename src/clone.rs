@@ -0,0 +1,177 @@
+//! Fetching plugins from Git servers that have no archive endpoint, such as plain SSH servers, by
+//! way of the `git` binary.
+
+use crate::{build, remote::RemoteRefs};
+use anyhow::{bail, Context, Result};
+use std::{
+    path::{Path, PathBuf},
+    process::Command,
+    time::Duration,
+};
+
+// Runs git, giving up rather than waiting for a password prompt nobody will answer.
+async fn git(args: &[&str], dir: Option<&Path>, timeout: Duration) -> Result<Vec<u8>> {
+    let mut command = Command::new("git");
+    command.args(args).env("GIT_TERMINAL_PROMPT", "0");
+
+    if let Some(dir) = dir {
+        command.current_dir(dir);
+    }
+
+    let name = format!("git {}", args.join(" "));
+    let output = build::output(&mut command, &name, timeout).await?;
+
+    if !output.status.success() {
+        bail!(
+            "‘{}’ failed with {}\n{}",
+            name,
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim_end()
+        );
+    }
+
+    Ok(output.stdout)
+}
+
+/// Lists the references the repo at the given URL has, without fetching anything else.
+pub async fn remote_refs(repo_url: &str, timeout: Duration) -> Result<RemoteRefs> {
+    let output = git(&["ls-remote", "--symref", repo_url], None, timeout).await?;
+
+    Ok(RemoteRefs::parse_ls_remote(&String::from_utf8_lossy(
+        &output,
+    )))
+}
+
+/// `RemoteRefs::resolve_or_default` for the repo at the given URL, asking `git ls-remote` for its
+/// references so that any URL Git understands works.
+pub async fn resolve(
+    repo_url: &str,
+    git_ref: Option<&str>,
+    timeout: Duration,
+) -> Result<(String, String)> {
+    remote_refs(repo_url, timeout)
+        .await?
+        .resolve_or_default(repo_url, git_ref)
+}
+
+// Each export gets a scratch repo of its own, since several plugins may be fetched at once.
+fn scratch_dir(repo_url: &str, commit: &str) -> PathBuf {
+    let key = crate::sha256_hex(format!("{}#{}", repo_url, commit).as_bytes());
+
+    std::env::temp_dir().join(format!(
+        "strand-clone-{}-{}",
+        std::process::id(),
+        &key[..16]
+    ))
+}
+
+/// Fetches just the given commit of the repo at the given URL and exports it as a tar archive,
+/// without any of Git’s own files. The commit can only be fetched directly if the server allows it,
+/// so the reference it was resolved from is fetched instead if that fails.
+pub async fn export(
+    repo_url: &str,
+    git_ref: Option<&str>,
+    commit: &str,
+    timeout: Duration,
+) -> Result<Vec<u8>> {
+    let dir = scratch_dir(repo_url, commit);
+
+    if dir.exists() {
+        std::fs::remove_dir_all(&dir)?;
+    }
+
+    std::fs::create_dir_all(&dir)?;
+
+    let result = async {
+        git(&["init", "--quiet", "--bare"], Some(&dir), timeout).await?;
+
+        let fetch = ["fetch", "--quiet", "--depth", "1", repo_url];
+
+        if let Err(e) = git(&[&fetch[..], &[commit]].concat(), Some(&dir), timeout).await {
+            match git_ref {
+                Some(git_ref) if git_ref != commit => {
+                    git(&[&fetch[..], &[git_ref]].concat(), Some(&dir), timeout).await?;
+                }
+                _ => return Err(e),
+            }
+        }
+
+        let fetched = git(&["rev-parse", "FETCH_HEAD^{commit}"], Some(&dir), timeout).await?;
+        let fetched = String::from_utf8_lossy(&fetched).trim().to_string();
+
+        if !fetched.starts_with(commit) {
+            bail!(
+                "fetched commit {} of {} instead of {} -- has ‘{}’ moved?",
+                fetched,
+                repo_url,
+                commit,
+                git_ref.unwrap_or(commit)
+            );
+        }
+
+        // The prefix gives the archive a single top-level directory, which is stripped off when it
+        // is unpacked just like those in archives from Git hosts.
+        git(
+            &["archive", "--format=tar", "--prefix=export/", "FETCH_HEAD"],
+            Some(&dir),
+            timeout,
+        )
+        .await
+    }
+    .await;
+
+    let _ = std::fs::remove_dir_all(&dir);
+
+    result.with_context(|| format!("failed to fetch {} with git", repo_url))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::archive::{self, Format};
+
+    fn run_git(args: &[&str], dir: &Path) {
+        let status = Command::new("git")
+            .args([
+                "-c",
+                "user.name=strand",
+                "-c",
+                "user.email=strand@example.com",
+            ])
+            .args(args)
+            .current_dir(dir)
+            .status()
+            .unwrap();
+        assert!(status.success());
+    }
+
+    #[async_std::test]
+    async fn test_resolve_and_export() {
+        let dir = std::env::temp_dir().join("strand-test-clone");
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let repo = dir.join("vim-foo");
+        std::fs::create_dir_all(repo.join("plugin")).unwrap();
+        std::fs::write(repo.join("plugin/foo.vim"), "\" foo").unwrap();
+        run_git(&["init", "--quiet", "--initial-branch=main"], &repo);
+        run_git(&["add", "."], &repo);
+        run_git(&["commit", "--quiet", "--message=Add plugin"], &repo);
+        run_git(&["tag", "--annotate", "--message=v1", "v1"], &repo);
+
+        let url = format!("file://{}", repo.display());
+        let timeout = Duration::from_secs(30);
+
+        let (git_ref, commit) = resolve(&url, None, timeout).await.unwrap();
+        assert_eq!(git_ref, "main");
+        assert_eq!(resolve(&url, Some("v1"), timeout).await.unwrap().1, commit);
+
+        let tar = export(&url, Some("v1"), &commit, timeout).await.unwrap();
+        assert_eq!(Format::detect(&tar, None), Some(Format::Tar));
+
+        archive::unpack(&tar, Format::Tar, &dir.join("export")).unwrap();
+        assert!(dir.join("export/export/plugin/foo.vim").is_file());
+        assert!(!dir.join("export/export/.git").exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
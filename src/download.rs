@@ -0,0 +1,347 @@
+//! Downloading over HTTP, retrying failures that are likely to be transient.
+
+use anyhow::{anyhow, Error, Result};
+use async_std::{future, task};
+use futures::io::AsyncReadExt;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use url::Url;
+
+// The first retry waits for around this long, and each one after that for twice as long as the
+// last, up to `MAX_DELAY`. Servers asking us to wait longer than that are not worth waiting for.
+const BASE_DELAY: Duration = Duration::from_millis(500);
+const MAX_DELAY: Duration = Duration::from_secs(60);
+
+// surf does not follow redirects by itself, so they are followed here, up to this many in a row.
+const MAX_REDIRECTS: usize = 10;
+
+/// How patient to be with servers.
+#[derive(Clone, Debug)]
+pub struct DownloadOptions {
+    /// How long a single request may take, including reading the response.
+    pub timeout: Duration,
+    /// How many times to retry a request that failed for a reason that may go away by itself.
+    pub retries: u32,
+}
+
+impl Default for DownloadOptions {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(60),
+            retries: 3,
+        }
+    }
+}
+
+/// A successful response.
+#[derive(Debug)]
+pub struct Download {
+    pub body: Vec<u8>,
+    pub content_type: Option<String>,
+}
+
+enum Failure {
+    // Connection errors, timeouts, 5xx and 429 responses, the last two possibly with a
+    // `Retry-After` header saying how long to wait.
+    Transient(Error, Option<Duration>),
+    Permanent(Error),
+}
+
+/// Told how many bytes of a response have arrived so far, and how many there are in total if the
+/// server said.
+pub type OnProgress<'a> = &'a (dyn Fn(u64, Option<u64>) + Sync);
+
+async fn try_get(
+    url: &str,
+    timeout: Duration,
+    on_progress: OnProgress<'_>,
+) -> Result<Download, Failure> {
+    let request = async {
+        let mut location = url.to_string();
+        let mut redirects = 0;
+
+        // Git hosts commonly redirect archive downloads to a different server.
+        let mut response = loop {
+            let response = surf::get(&location)
+                .await
+                .map_err(|e| Failure::Transient(anyhow!(e), None))?;
+
+            let next = match response.header("Location") {
+                Some(next) if response.status().is_redirection() => next,
+                _ => break response,
+            };
+
+            if redirects == MAX_REDIRECTS {
+                let error = anyhow!("too many redirects downloading {}", url);
+
+                return Err(Failure::Permanent(error));
+            }
+
+            // The new location may be relative to the one that redirected to it.
+            location = Url::parse(&location)
+                .and_then(|base| base.join(next))
+                .map_err(|e| {
+                    let error = anyhow!("{} redirected to invalid URL ‘{}’ -- {}", url, next, e);
+
+                    Failure::Permanent(error)
+                })?
+                .into_string();
+            redirects += 1;
+        };
+        let status = response.status();
+
+        if status.is_server_error() || status.as_u16() == 429 {
+            let retry_after = response.header("Retry-After").and_then(parse_retry_after);
+            let error = anyhow!("server responded with {} for {}", status, url);
+
+            return Err(Failure::Transient(error, retry_after));
+        }
+
+        if !status.is_success() {
+            let error = anyhow!("server responded with {} for {}", status, url);
+
+            return Err(Failure::Permanent(error));
+        }
+
+        let content_type = response.header("Content-Type").map(String::from);
+        let total = response
+            .header("Content-Length")
+            .and_then(|length| length.parse().ok());
+
+        let mut body = Vec::new();
+        let mut chunk = vec![0; 64 * 1024];
+
+        loop {
+            let n = response
+                .read(&mut chunk)
+                .await
+                .map_err(|e| Failure::Transient(e.into(), None))?;
+
+            if n == 0 {
+                break;
+            }
+
+            body.extend_from_slice(&chunk[..n]);
+            on_progress(body.len() as u64, total);
+        }
+
+        Ok(Download { body, content_type })
+    };
+
+    match future::timeout(timeout, request).await {
+        Ok(result) => result,
+        Err(_) => Err(Failure::Transient(
+            anyhow!(
+                "timed out after {} seconds downloading {}",
+                timeout.as_secs(),
+                url
+            ),
+            None,
+        )),
+    }
+}
+
+/// Downloads the given URL, retrying with exponential backoff if it fails for a reason that may be
+/// temporary.
+pub async fn get(
+    url: &str,
+    options: &DownloadOptions,
+    on_progress: OnProgress<'_>,
+) -> Result<Download> {
+    let mut attempt = 0;
+
+    loop {
+        let (error, retry_after) = match try_get(url, options.timeout, on_progress).await {
+            Ok(download) => return Ok(download),
+            Err(Failure::Permanent(error)) => return Err(error),
+            Err(Failure::Transient(error, retry_after)) => (error, retry_after),
+        };
+
+        if attempt >= options.retries || retry_after.is_some_and(|delay| delay > MAX_DELAY) {
+            return Err(match attempt {
+                0 => error,
+                _ => error.context(format!("giving up after {} attempts", attempt + 1)),
+            });
+        }
+
+        task::sleep(retry_after.unwrap_or_else(|| backoff(attempt))).await;
+        attempt += 1;
+    }
+}
+
+// Half of the delay is fixed and the other half random, so that plugins that failed together do
+// not all retry at the same moment.
+fn backoff(attempt: u32) -> Duration {
+    use std::{
+        collections::hash_map::RandomState,
+        hash::{BuildHasher, Hasher},
+    };
+
+    let delay = BASE_DELAY
+        .checked_mul(1 << attempt.min(16))
+        .map_or(MAX_DELAY, |delay| delay.min(MAX_DELAY));
+
+    // The standard library has no random number generator, but it does randomly seed its hashers.
+    let random = RandomState::new().build_hasher().finish() as f64 / u64::MAX as f64;
+
+    delay / 2 + (delay / 2).mul_f64(random)
+}
+
+// `Retry-After` is either a number of seconds or an HTTP date such as
+// ‘Wed, 21 Oct 2015 07:28:00 GMT’.
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    let value = value.trim();
+
+    if let Ok(seconds) = value.parse() {
+        return Some(Duration::from_secs(seconds));
+    }
+
+    let date = parse_http_date(value)?;
+
+    Some(
+        date.duration_since(SystemTime::now())
+            .unwrap_or(Duration::ZERO),
+    )
+}
+
+fn parse_http_date(value: &str) -> Option<SystemTime> {
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+
+    let mut parts = value.split_once(", ")?.1.split(' ');
+
+    let day: u64 = parts.next()?.parse().ok()?;
+    let month = parts.next()?;
+    let month = MONTHS.iter().position(|&m| m == month)? as u64 + 1;
+    let year: u64 = parts.next()?.parse().ok()?;
+
+    let mut time = parts.next()?.split(':').map(|n| n.parse::<u64>().ok());
+    let (hours, minutes, seconds) = (time.next()??, time.next()??, time.next()??);
+
+    if parts.next() != Some("GMT") {
+        return None;
+    }
+
+    // The date comes from the server, so anything out of range is rejected rather than trusted
+    // not to overflow the sums below.
+    let in_range = (1970..=9999).contains(&year)
+        && (1..=31).contains(&day)
+        && hours < 24
+        && minutes < 60
+        && seconds <= 60;
+
+    if !in_range {
+        return None;
+    }
+
+    // Days since the epoch for a date in the Gregorian calendar, counting years from March so
+    // that leap days come at the end.
+    let (year, month) = if month <= 2 {
+        (year - 1, month + 9)
+    } else {
+        (year, month - 3)
+    };
+    let era_days = (year / 400) * 146_097;
+    let year_of_era = year % 400;
+    let day_of_year = (153 * month + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era_days + day_of_era - 719_468;
+
+    let seconds = days * 86_400 + hours * 3_600 + minutes * 60 + seconds;
+
+    Some(UNIX_EPOCH + Duration::from_secs(seconds))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server::{self, Response};
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    #[test]
+    fn test_parse_retry_after() {
+        assert_eq!(parse_retry_after("120"), Some(Duration::from_secs(120)));
+        assert_eq!(
+            parse_http_date("Wed, 21 Oct 2015 07:28:00 GMT"),
+            Some(UNIX_EPOCH + Duration::from_secs(1_445_412_480))
+        );
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"),
+            Some(Duration::ZERO)
+        );
+        assert_eq!(parse_retry_after("soon"), None);
+        assert_eq!(parse_http_date("Sun, 0 Mar 2015 07:28:00 GMT"), None);
+        assert_eq!(parse_http_date("Wed, 21 Oct 2015 25:28:00 GMT"), None);
+        assert_eq!(
+            parse_http_date("Wed, 21 Oct 99999999999999 07:28:00 GMT"),
+            None
+        );
+    }
+
+    #[async_std::test]
+    async fn test_get_retries() {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = requests.clone();
+
+        let server = test_server::serve(move |path| {
+            let n = counter.fetch_add(1, Ordering::SeqCst);
+
+            match path {
+                "/flaky" if n == 0 => Response {
+                    status: 503,
+                    headers: vec![("Retry-After".into(), "0".into())],
+                    body: Vec::new(),
+                },
+                "/flaky" => Response::ok("archive"),
+                _ => Response::not_found(),
+            }
+        });
+
+        let options = DownloadOptions::default();
+
+        let download = get(&format!("{}/flaky", server), &options, &|_, _| ())
+            .await
+            .unwrap();
+        assert_eq!(download.body, b"archive");
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+
+        // Missing files are not going to appear by trying again.
+        let error = get(&format!("{}/missing", server), &options, &|_, _| ())
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("404"));
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+    }
+
+    #[async_std::test]
+    async fn test_get_redirects() {
+        let server = test_server::serve(move |path| {
+            let redirect = |location: &str| Response {
+                status: 302,
+                headers: vec![("Location".into(), location.into())],
+                body: Vec::new(),
+            };
+
+            match path {
+                "/user/repo/archive/v1.tar.gz" => redirect("/codeload/user/repo/tar.gz/v1"),
+                "/codeload/user/repo/tar.gz/v1" => Response::ok("archive"),
+                "/loop" => redirect("loop"),
+                _ => Response::not_found(),
+            }
+        });
+
+        let options = DownloadOptions::default();
+
+        let url = format!("{}/user/repo/archive/v1.tar.gz", server);
+        let download = get(&url, &options, &|_, _| ()).await.unwrap();
+        assert_eq!(download.body, b"archive");
+
+        let error = get(&format!("{}/loop", server), &options, &|_, _| ())
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("too many redirects"));
+    }
+}
@@ -0,0 +1,302 @@
+//! Adding plugins to and removing them from the config file. The file is edited as text rather
+//! than parsed and written back out, so that its comments and layout are kept.
+
+use crate::{Config, Plugin, PluginParseError};
+use anyhow::{anyhow, bail, Context, Result};
+use std::{ops::Range, path::Path, str::FromStr};
+
+/// A plugin given on the command line, along with how the user wrote it so that it goes into the
+/// config file the same way.
+pub struct PluginArg {
+    pub spec: String,
+    pub plugin: Plugin,
+}
+
+impl FromStr for PluginArg {
+    type Err = PluginParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(PluginArg {
+            spec: s.into(),
+            plugin: s.parse()?,
+        })
+    }
+}
+
+// Where the `plugins:` list is in the config file, by line number.
+struct PluginsList {
+    key: usize,
+    /// How far the list’s `- ` markers are indented, unless it has no entries yet.
+    indent: Option<usize>,
+    /// The lines of each entry, not counting any blank lines or comments after it.
+    entries: Vec<Range<usize>>,
+}
+
+fn indent_of(line: &str) -> usize {
+    line.len() - line.trim_start_matches(' ').len()
+}
+
+fn is_blank_or_comment(line: &str) -> bool {
+    let line = line.trim();
+    line.is_empty() || line.starts_with('#')
+}
+
+fn find_plugins_list(lines: &[&str]) -> Result<PluginsList> {
+    let key = lines
+        .iter()
+        .position(|line| line.starts_with("plugins:"))
+        .ok_or_else(|| anyhow!("config file has no ‘plugins:’ list"))?;
+
+    let rest = lines[key]["plugins:".len()..].trim();
+    let mut list = PluginsList {
+        key,
+        indent: None,
+        entries: Vec::new(),
+    };
+
+    if rest.starts_with("[]") {
+        return Ok(list);
+    }
+
+    if !rest.is_empty() && !rest.starts_with('#') {
+        bail!("config file’s plugins are not written one per line as ‘- ’ entries");
+    }
+
+    for (i, line) in lines.iter().enumerate().skip(key + 1) {
+        if is_blank_or_comment(line) {
+            continue;
+        }
+
+        let indent = indent_of(line);
+        let is_entry = line.trim_start().starts_with('-');
+
+        // The list ends at the next top-level key. Its entries may start at the very beginning of
+        // the line, though.
+        if indent == 0 && !is_entry {
+            break;
+        }
+
+        match list.indent {
+            Some(list_indent) if is_entry && indent == list_indent => list.entries.push(i..i + 1),
+            None if is_entry => {
+                list.indent = Some(indent);
+                list.entries.push(i..i + 1);
+            }
+            _ => match list.entries.last_mut() {
+                Some(entry) => entry.end = i + 1,
+                None => bail!("could not make sense of line {} of the config file", i + 1),
+            },
+        }
+    }
+
+    Ok(list)
+}
+
+// Plugin IDs are written as plain YAML strings where possible, and quoted if they would otherwise
+// mean something else.
+fn yaml_string(s: &str) -> String {
+    let needs_quotes = s.is_empty()
+        || s.starts_with(|c: char| c.is_whitespace() || "-?:,[]{}#&*!|>'\"%@`".contains(c))
+        || s.ends_with(|c: char| c.is_whitespace() || c == ':')
+        || s.contains(": ")
+        || s.contains(" #");
+
+    if needs_quotes {
+        format!("'{}'", s.replace('\'', "''"))
+    } else {
+        s.into()
+    }
+}
+
+fn plugin_ids(text: &str) -> Result<Vec<String>> {
+    let config: Config = yaml::from_str(text)?;
+
+    Ok(config.plugins.iter().map(|p| p.source.id()).collect())
+}
+
+// Makes sure that an edit did what it was meant to, in case the config file is laid out in a way
+// that fooled us.
+fn check_edit(text: &str, expected: &[String]) -> Result<()> {
+    match plugin_ids(text) {
+        Ok(ids) if ids == expected => Ok(()),
+        _ => bail!("could not safely edit the config file -- please make the change by hand"),
+    }
+}
+
+fn join_lines(text: &str, lines: &[String]) -> String {
+    let newline = if text.contains("\r\n") { "\r\n" } else { "\n" };
+    let mut joined = lines.join(newline);
+    joined.push_str(newline);
+
+    joined
+}
+
+/// Returns the config file with the given plugins added to the end of its `plugins:` list. `config`
+/// is the config with its included files and overlay merged in, which the plugins must not be in
+/// either.
+pub fn add_plugins(text: &str, config: &Config, plugins: &[PluginArg]) -> Result<String> {
+    let mut ids = plugin_ids(text).context("failed to parse config file")?;
+
+    for arg in plugins {
+        let id = arg.plugin.id();
+
+        if ids.contains(&id) {
+            bail!("{} is already in the config file", id);
+        }
+
+        // The new entry would otherwise replace the one from the other file when they are merged.
+        if config.plugins.iter().any(|p| p.source.id() == id) {
+            bail!(
+                "{} is already in a file the config file includes or in its overlay",
+                id
+            );
+        }
+
+        ids.push(id);
+    }
+
+    let mut lines: Vec<String> = text.lines().map(String::from).collect();
+    let list = find_plugins_list(&text.lines().collect::<Vec<_>>())?;
+
+    if list.entries.is_empty() {
+        lines[list.key] = "plugins:".into();
+    }
+
+    let indent = " ".repeat(list.indent.unwrap_or(2));
+    let at = list.entries.last().map_or(list.key + 1, |entry| entry.end);
+    let new_lines = plugins.iter().map(|arg| {
+        format!(
+            "{}- {}: {}",
+            indent,
+            arg.plugin.kind(),
+            yaml_string(&arg.spec)
+        )
+    });
+
+    lines.splice(at..at, new_lines);
+
+    let edited = join_lines(text, &lines);
+    check_edit(&edited, &ids)?;
+
+    Ok(edited)
+}
+
+/// Returns the config file without the plugin with the given ID.
+pub fn remove_plugin(text: &str, id: &str) -> Result<String> {
+    let mut ids = plugin_ids(text).context("failed to parse config file")?;
+    let index = ids.iter().position(|other| other == id).ok_or_else(|| {
+        anyhow!(
+            "{} is not in the config file itself -- remove it from the file it comes from",
+            id
+        )
+    })?;
+    ids.remove(index);
+
+    let mut lines: Vec<String> = text.lines().map(String::from).collect();
+    let list = find_plugins_list(&text.lines().collect::<Vec<_>>())?;
+    let entry = list
+        .entries
+        .get(index)
+        .cloned()
+        .ok_or_else(|| anyhow!("could not find {} in the config file", id))?;
+
+    lines.drain(entry);
+
+    // An empty list has to be written out, since YAML would otherwise read it as null.
+    if ids.is_empty() {
+        lines[list.key] = "plugins: []".into();
+    }
+
+    let edited = join_lines(text, &lines);
+    check_edit(&edited, &ids)?;
+
+    Ok(edited)
+}
+
+/// Replaces the config file, without leaving it half-written if that fails.
+pub async fn write_config(path: &Path, text: &str) -> Result<()> {
+    use async_std::fs;
+
+    let tmp_path = path.with_extension("tmp");
+    fs::write(&tmp_path, text).await?;
+    fs::rename(&tmp_path, path).await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = "\
+pack_dir: ~/.vim/pack/strand
+
+plugins:
+  # Essentials
+  - Git: tpope/vim-surround
+  - Git: junegunn/fzf
+    run: ./install --bin
+
+  # Not so essential
+  - Archive: https://example.com/vim-qlist.tar.gz
+
+run_timeout: 600
+";
+
+    #[test]
+    fn test_add_plugins() {
+        let config: Config = yaml::from_str(CONFIG).unwrap();
+        let plugins = [
+            "tpope/vim-repeat:v1.2".parse().unwrap(),
+            "ssh://git@git.corp.example/vim-foo.git#main"
+                .parse()
+                .unwrap(),
+        ];
+
+        assert_eq!(
+            add_plugins(CONFIG, &config, &plugins).unwrap(),
+            CONFIG.replace(
+                "vim-qlist.tar.gz\n",
+                "vim-qlist.tar.gz\n  - Git: tpope/vim-repeat:v1.2\n  - GitClone: ssh://git@git.corp.example/vim-foo.git#main\n"
+            )
+        );
+
+        let error = add_plugins(CONFIG, &config, &["junegunn/fzf".parse().unwrap()]).unwrap_err();
+        assert!(error.to_string().contains("already in the config file"));
+
+        let included: Config =
+            yaml::from_str("pack_dir: x\nplugins:\n  - Git: tpope/vim-repeat\n").unwrap();
+        let error =
+            add_plugins(CONFIG, &included, &["tpope/vim-repeat".parse().unwrap()]).unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("already in a file the config file includes"));
+
+        assert_eq!(
+            add_plugins(
+                "pack_dir: x\nplugins: []\n",
+                &yaml::from_str("pack_dir: x\nplugins: []\n").unwrap(),
+                &["tpope/vim-repeat".parse().unwrap()]
+            )
+            .unwrap(),
+            "pack_dir: x\nplugins:\n  - Git: tpope/vim-repeat\n"
+        );
+    }
+
+    #[test]
+    fn test_remove_plugin() {
+        assert_eq!(
+            remove_plugin(CONFIG, "github@junegunn/fzf").unwrap(),
+            CONFIG.replace("  - Git: junegunn/fzf\n    run: ./install --bin\n", "")
+        );
+        assert_eq!(
+            remove_plugin(
+                "pack_dir: x\nplugins:\n- Git: tpope/vim-repeat\n",
+                "github@tpope/vim-repeat"
+            )
+            .unwrap(),
+            "pack_dir: x\nplugins: []\n"
+        );
+        assert!(remove_plugin(CONFIG, "github@tpope/vim-repeat").is_err());
+    }
+}
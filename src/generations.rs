@@ -0,0 +1,416 @@
+//! Earlier versions of the pack directory, kept so that a bad update can be rolled back without
+//! downloading anything. Each version is a numbered generation: the pack directory in use is
+//! stamped with its number, and the ones before it are moved aside into a sibling directory rather
+//! than deleted. A generation gets its own copy of the files it shares with the pack directory
+//! that replaced it, so that editing the plugins in use does not change it too.
+
+use crate::{list::format_time, list::print_table, remove_path, staging::sibling, State};
+use anyhow::{anyhow, bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+// Hidden so that Vim does not mistake it for a plugin, like the state file.
+const STAMP_FILE: &str = ".strand-generation.yaml";
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct GenerationStamp {
+    pub number: u32,
+    /// When the generation was installed, in seconds since the Unix epoch. Pack directories from
+    /// before strand kept generations are given a number when they are first moved aside, but
+    /// there is no telling when they were installed.
+    #[serde(default)]
+    pub created_at: Option<u64>,
+}
+
+/// A row of `strand generations`.
+#[derive(Serialize)]
+pub struct Generation {
+    #[serde(flatten)]
+    pub stamp: GenerationStamp,
+    /// Whether this is the pack directory in use.
+    pub current: bool,
+    /// What the generation’s state file says it has installed.
+    pub plugins: Vec<GenerationPlugin>,
+    #[serde(skip)]
+    pub path: PathBuf,
+}
+
+#[derive(Serialize)]
+pub struct GenerationPlugin {
+    pub id: String,
+    #[serde(rename = "ref")]
+    pub git_ref: Option<String>,
+    pub commit: Option<String>,
+    pub sha256: Option<String>,
+}
+
+pub(crate) fn generations_dir(pack_dir: &Path) -> Result<PathBuf> {
+    sibling(pack_dir, "generations")
+}
+
+fn read_stamp(dir: &Path) -> Result<Option<GenerationStamp>> {
+    let path = dir.join(STAMP_FILE);
+
+    if !path.exists() {
+        return Ok(None);
+    }
+
+    let stamp = fs::read_to_string(&path)?;
+    let stamp = yaml::from_str(&stamp)
+        .with_context(|| format!("failed to parse generation stamp at {}", path.display()))?;
+
+    Ok(Some(stamp))
+}
+
+// Replaced rather than overwritten, since the stamp may be hard-linked into another generation.
+fn write_stamp(dir: &Path, stamp: GenerationStamp) -> Result<()> {
+    let path = dir.join(STAMP_FILE);
+    let tmp_path = path.with_extension("tmp");
+    fs::write(&tmp_path, yaml::to_string(&stamp)?)?;
+    fs::rename(&tmp_path, &path)?;
+
+    Ok(())
+}
+
+// Returns the earlier generations, oldest first.
+fn earlier(pack_dir: &Path) -> Result<Vec<(u32, PathBuf)>> {
+    let dir = generations_dir(pack_dir)?;
+    let mut generations = Vec::new();
+
+    if !dir.is_dir() {
+        return Ok(generations);
+    }
+
+    for entry in fs::read_dir(&dir)? {
+        let entry = entry?;
+
+        // Anything else is a generation part of the way through being moved or removed.
+        if let Some(number) = entry.file_name().to_str().and_then(|n| n.parse().ok()) {
+            generations.push((number, entry.path()));
+        }
+    }
+
+    generations.sort();
+
+    Ok(generations)
+}
+
+// Lists every generation there is, oldest first, including the pack directory itself if it has
+// been stamped. Their plugins are left out.
+fn stamped(pack_dir: &Path) -> Result<Vec<Generation>> {
+    let mut generations = Vec::new();
+
+    for (number, path) in earlier(pack_dir)? {
+        let stamp = read_stamp(&path)?.unwrap_or(GenerationStamp {
+            number,
+            created_at: None,
+        });
+
+        generations.push(Generation {
+            stamp,
+            current: false,
+            plugins: Vec::new(),
+            path,
+        });
+    }
+
+    if let Some(stamp) = read_stamp(pack_dir)? {
+        generations.push(Generation {
+            stamp,
+            current: true,
+            plugins: Vec::new(),
+            path: pack_dir.into(),
+        });
+        generations.sort_by_key(|generation| generation.stamp.number);
+    }
+
+    Ok(generations)
+}
+
+/// Lists every generation there is along with its plugins, oldest first.
+pub async fn list(pack_dir: &Path) -> Result<Vec<Generation>> {
+    let mut generations = stamped(pack_dir)?;
+
+    for generation in &mut generations {
+        let state = State::read(&generation.path).await?.unwrap_or_default();
+
+        generation.plugins = state
+            .plugins
+            .into_iter()
+            .map(|(id, plugin)| GenerationPlugin {
+                id,
+                git_ref: plugin.git_ref,
+                commit: plugin.version.commit,
+                sha256: plugin.version.sha256,
+            })
+            .collect();
+    }
+
+    Ok(generations)
+}
+
+/// Prints a line for each generation, saying when it was installed.
+pub fn print_generations(generations: &[Generation]) {
+    let header = ["GENERATION", "INSTALLED", "PLUGINS", ""].map(String::from);
+    let rows: Vec<_> = std::iter::once(header)
+        .chain(generations.iter().map(|generation| {
+            [
+                generation.stamp.number.to_string(),
+                generation
+                    .stamp
+                    .created_at
+                    .map(format_time)
+                    .unwrap_or_default(),
+                generation.plugins.len().to_string(),
+                if generation.current { "current" } else { "" }.into(),
+            ]
+        }))
+        .collect();
+
+    print_table(&rows);
+}
+
+fn latest_number(pack_dir: &Path) -> Result<u32> {
+    let generations = stamped(pack_dir)?;
+
+    Ok(generations
+        .iter()
+        .map(|g| g.stamp.number)
+        .max()
+        .unwrap_or(0))
+}
+
+/// Stamps a new pack directory with the next generation number before it is put into use.
+pub(crate) fn stamp_new(pack_dir: &Path, new_pack_dir: &Path) -> Result<()> {
+    let mut number = latest_number(pack_dir)? + 1;
+
+    // The pack directory being replaced needs a number of its own to be kept under.
+    if pack_dir.exists() && read_stamp(pack_dir)?.is_none() {
+        number += 1;
+    }
+
+    let created_at = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_secs())
+        .ok();
+
+    write_stamp(new_pack_dir, GenerationStamp { number, created_at })
+}
+
+#[cfg(unix)]
+fn is_shared(metadata: &fs::Metadata) -> bool {
+    use std::os::unix::fs::MetadataExt;
+
+    metadata.nlink() > 1
+}
+
+#[cfg(not(unix))]
+fn is_shared(_metadata: &fs::Metadata) -> bool {
+    true
+}
+
+// Replaces every file that is hard-linked elsewhere with a copy of its own. The staging directory
+// is built from hard links, so unchanged files are the same files in both pack directories, and
+// anything that writes to them in place (Vim’s `:helptags`, plugins that keep data in their own
+// directory, the user) would change the earlier generation as well.
+fn unshare_tree(dir: &Path) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        let path = entry.path();
+
+        if file_type.is_dir() {
+            unshare_tree(&path)?;
+        } else if file_type.is_file() && is_shared(&entry.metadata()?) {
+            let tmp_path = dir.join(format!(
+                ".{}.strand-copy",
+                entry.file_name().to_string_lossy()
+            ));
+            fs::copy(&path, &tmp_path)?;
+            fs::rename(&tmp_path, &path)?;
+        }
+    }
+
+    Ok(())
+}
+
+// Moves a pack directory that has just been replaced in with the earlier generations.
+fn put_aside(pack_dir: &Path, old_pack_dir: &Path) -> Result<()> {
+    let number = match read_stamp(old_pack_dir)? {
+        Some(stamp) => stamp.number,
+        // Committing a new pack directory leaves a number free for the one it replaced, but
+        // anything else gets a new number.
+        None => {
+            let latest = latest_number(pack_dir)?;
+            let current = read_stamp(pack_dir)?.map(|stamp| stamp.number);
+            let free = latest.saturating_sub(1);
+            let taken = free == 0
+                || current == Some(free)
+                || generations_dir(pack_dir)?.join(free.to_string()).exists();
+            let number = if taken { latest + 1 } else { free };
+
+            let stamp = GenerationStamp {
+                number,
+                created_at: None,
+            };
+            write_stamp(old_pack_dir, stamp)?;
+            number
+        }
+    };
+
+    unshare_tree(old_pack_dir)
+        .with_context(|| format!("failed to copy shared files in {}", old_pack_dir.display()))?;
+
+    let dir = generations_dir(pack_dir)?;
+    fs::create_dir_all(&dir)?;
+    fs::rename(old_pack_dir, dir.join(number.to_string()))?;
+
+    Ok(())
+}
+
+/// Keeps the pack directory that has just been replaced as an earlier generation, then deletes
+/// the oldest generations until only `keep` are left. Keeping none deletes it straight away.
+pub(crate) async fn retire(pack_dir: &Path, old_pack_dir: &Path, keep: usize) -> Result<()> {
+    if keep == 0 {
+        remove_path(old_pack_dir).await?;
+    } else {
+        put_aside(pack_dir, old_pack_dir)?;
+    }
+
+    let generations = earlier(pack_dir)?;
+    let excess = generations.len().saturating_sub(keep);
+
+    for (_, path) in generations.iter().take(excess) {
+        remove_path(path).await?;
+    }
+
+    Ok(())
+}
+
+/// Puts an earlier generation back in place of the pack directory, which becomes an earlier
+/// generation itself. Without a number, the newest generation older than the current one is used.
+pub async fn rollback(pack_dir: &Path, number: Option<u32>) -> Result<GenerationStamp> {
+    let generations = stamped(pack_dir)?;
+    let current = generations
+        .iter()
+        .find(|g| g.current)
+        .map(|g| g.stamp.number);
+
+    let target = match number {
+        Some(number) => generations
+            .iter()
+            .find(|g| g.stamp.number == number)
+            .ok_or_else(|| anyhow!("there is no generation {}", number))?,
+        None => generations
+            .iter()
+            .rev()
+            .find(|g| current.is_none_or(|current| g.stamp.number < current))
+            .ok_or_else(|| anyhow!("there is no earlier generation to roll back to"))?,
+    };
+
+    if target.current {
+        bail!("generation {} is already in use", target.stamp.number);
+    }
+
+    // The state file has to come along, so that the next sync knows what is installed.
+    if State::read(&target.path).await?.is_none() {
+        bail!(
+            "generation {} has no state file, so it cannot be rolled back to",
+            target.stamp.number
+        );
+    }
+
+    let backup = sibling(pack_dir, "old")?;
+
+    if backup.exists() {
+        remove_path(&backup).await?;
+    }
+
+    let had_pack_dir = pack_dir.exists();
+
+    if had_pack_dir {
+        fs::rename(pack_dir, &backup)?;
+    }
+
+    if let Err(e) = fs::rename(&target.path, pack_dir) {
+        if had_pack_dir {
+            fs::rename(&backup, pack_dir)?;
+        }
+
+        return Err(e).with_context(|| {
+            format!(
+                "failed to move generation {} into {}",
+                target.stamp.number,
+                pack_dir.display()
+            )
+        });
+    }
+
+    if had_pack_dir {
+        put_aside(pack_dir, &backup)?;
+    }
+
+    Ok(target.stamp)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Staging;
+
+    #[async_std::test]
+    async fn test_rollback() {
+        let dir = std::env::temp_dir().join("strand-test-generations");
+        let _ = fs::remove_dir_all(&dir);
+
+        let pack_dir = dir.join("strand");
+        let plugin = pack_dir.join("start/vim-surround/surround.vim");
+
+        for version in &["1", "2", "3"] {
+            let staging = Staging::new(&pack_dir, true).await.unwrap();
+            let file = staging.path().join("start/vim-surround/surround.vim");
+            let _ = fs::remove_file(&file);
+            fs::create_dir_all(file.parent().unwrap()).unwrap();
+            fs::write(&file, version).unwrap();
+            State::default().write(staging.path()).await.unwrap();
+            staging.commit(2).await.unwrap();
+        }
+
+        let numbers = |pack_dir| {
+            let generations = stamped(pack_dir).unwrap();
+            let numbers = generations.iter().map(|g| (g.stamp.number, g.current));
+            numbers.collect::<Vec<_>>()
+        };
+
+        assert_eq!(numbers(&pack_dir), [(1, false), (2, false), (3, true)]);
+        assert_eq!(fs::read_to_string(&plugin).unwrap(), "3");
+
+        // Rolling back goes to the generation before the current one each time…
+        assert_eq!(rollback(&pack_dir, None).await.unwrap().number, 2);
+        assert_eq!(fs::read_to_string(&plugin).unwrap(), "2");
+        assert_eq!(rollback(&pack_dir, None).await.unwrap().number, 1);
+        assert_eq!(fs::read_to_string(&plugin).unwrap(), "1");
+        assert!(rollback(&pack_dir, None).await.is_err());
+
+        // …but any generation can be picked.
+        rollback(&pack_dir, Some(3)).await.unwrap();
+        assert_eq!(fs::read_to_string(&plugin).unwrap(), "3");
+
+        // Only so many earlier generations are kept.
+        let staging = Staging::new(&pack_dir, true).await.unwrap();
+        staging.commit(2).await.unwrap();
+        assert_eq!(numbers(&pack_dir), [(2, false), (3, false), (4, true)]);
+
+        // Editing a file in place leaves the earlier generations alone.
+        fs::write(&plugin, "edited").unwrap();
+        let earlier = generations_dir(&pack_dir).unwrap().join("3");
+        let earlier_plugin = earlier.join("start/vim-surround/surround.vim");
+        assert_eq!(fs::read_to_string(&earlier_plugin).unwrap(), "3");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,227 @@
+//! A reimplementation of Vim’s `:helptags`, so that `:help` works for installed plugins without
+//! having to start Vim.
+
+use anyhow::{Context, Result};
+use std::{collections::BTreeMap, fs, path::Path};
+
+// English help files end in ‘.txt’ and go in ‘tags’, while translated ones end in ‘.xxx’ (e.g.
+// ‘.dex’ for German) and go in ‘tags-xx’.
+fn language(file_name: &str) -> Option<String> {
+    let bytes = file_name.as_bytes();
+    let len = bytes.len();
+
+    if len <= 4 || bytes[len - 4] != b'.' {
+        return None;
+    }
+
+    let extension = &bytes[len - 3..];
+
+    if extension.eq_ignore_ascii_case(b"txt") {
+        Some("en".into())
+    } else if extension[0].is_ascii_alphabetic()
+        && extension[1].is_ascii_alphabetic()
+        && extension[2].eq_ignore_ascii_case(&b'x')
+    {
+        Some(String::from_utf8_lossy(&extension[..2]).to_ascii_lowercase())
+    } else {
+        None
+    }
+}
+
+/// Finds the `*tags*` defined on a line of a help file. Like Vim, a tag must be preceded by
+/// whitespace or the start of the line, followed by whitespace or the end of the line, and may
+/// not contain spaces, tabs or bars.
+fn tags_in_line(line: &[u8]) -> Vec<&[u8]> {
+    let mut tags = Vec::new();
+    let mut start = line.iter().position(|&c| c == b'*');
+
+    while let Some(p1) = start {
+        let p2 = line[p1 + 1..]
+            .iter()
+            .position(|&c| c == b'*')
+            .map(|i| p1 + 1 + i);
+
+        if let Some(p2) = p2 {
+            let tag = &line[p1 + 1..p2];
+            let valid_chars = !tag.is_empty() && !tag.iter().any(|c| b" \t|".contains(c));
+            let space_before = p1 == 0 || line[p1 - 1] == b' ' || line[p1 - 1] == b'\t';
+            let space_after = line.get(p2 + 1).is_none_or(|c| b" \t\r\n".contains(c));
+
+            if valid_chars && space_before && space_after {
+                tags.push(tag);
+            }
+        }
+
+        // The closing ‘*’ may be the opening one of the next tag.
+        start = p2;
+    }
+
+    tags
+}
+
+// Vim only looks at the first line of each file to decide whether it is UTF-8.
+fn first_line_is_utf8(contents: &[u8]) -> bool {
+    let first_line = contents.split(|&c| c == b'\n').next().unwrap_or_default();
+
+    !first_line.is_ascii() && std::str::from_utf8(first_line).is_ok()
+}
+
+/// Told about problems with help files that Vim would warn about but that do not stop the tags
+/// file from being written.
+pub type OnWarning<'a> = &'a dyn Fn(&str);
+
+fn write_tags_file(
+    doc_dir: &Path,
+    files: &[String],
+    tags_file: &str,
+    on_warning: OnWarning<'_>,
+) -> Result<()> {
+    let mut entries: Vec<(Vec<u8>, &str)> = Vec::new();
+    let mut utf8 = None;
+
+    for file in files {
+        let contents = fs::read(doc_dir.join(file))?;
+
+        let this_utf8 = first_line_is_utf8(&contents);
+        if *utf8.get_or_insert(this_utf8) != this_utf8 {
+            on_warning(&format!(
+                "Warning: mix of help file encodings in {}",
+                doc_dir.join(file).display()
+            ));
+        }
+
+        for line in contents.split(|&c| c == b'\n') {
+            for tag in tags_in_line(line) {
+                entries.push((tag.to_vec(), file));
+            }
+        }
+    }
+
+    entries.sort();
+
+    // Vim reports duplicate tags but still writes them all out.
+    for pair in entries.windows(2) {
+        if pair[0].0 == pair[1].0 {
+            on_warning(&format!(
+                "Warning: duplicate tag ‘{}’ in {}",
+                String::from_utf8_lossy(&pair[1].0),
+                doc_dir.join(pair[1].1).display()
+            ));
+        }
+    }
+
+    let mut output = Vec::new();
+
+    if utf8 == Some(true) {
+        output.extend_from_slice(b"!_TAG_FILE_ENCODING\tutf-8\t//\n");
+    }
+
+    for (tag, file) in &entries {
+        output.extend_from_slice(tag);
+        output.push(b'\t');
+        output.extend_from_slice(file.as_bytes());
+        output.extend_from_slice(b"\t/*");
+
+        // The search pattern has to escape the characters that are special in it.
+        for &c in tag {
+            if c == b'\\' || c == b'/' {
+                output.push(b'\\');
+            }
+            output.push(c);
+        }
+
+        output.extend_from_slice(b"*\n");
+    }
+
+    fs::write(doc_dir.join(tags_file), output)?;
+
+    Ok(())
+}
+
+/// Generates the `tags` file (and `tags-xx` files for translated help) for a plugin’s `doc`
+/// directory.
+pub fn generate(doc_dir: &Path, on_warning: OnWarning<'_>) -> Result<()> {
+    let mut languages: BTreeMap<String, Vec<String>> = BTreeMap::new();
+
+    for entry in fs::read_dir(doc_dir)? {
+        let entry = entry?;
+
+        if !entry.file_type()?.is_file() {
+            continue;
+        }
+
+        if let Ok(file_name) = entry.file_name().into_string() {
+            if let Some(language) = language(&file_name) {
+                languages.entry(language).or_default().push(file_name);
+            }
+        }
+    }
+
+    for (language, mut files) in languages {
+        files.sort();
+
+        let tags_file = match language.as_str() {
+            "en" => "tags".into(),
+            _ => format!("tags-{}", language),
+        };
+
+        write_tags_file(doc_dir, &files, &tags_file, on_warning).with_context(|| {
+            format!("failed to generate {}", doc_dir.join(&tags_file).display())
+        })?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tags_in_line() {
+        assert_eq!(
+            tags_in_line(b"*surround.txt*  Plugin for deleting parentheses *surround*"),
+            vec![&b"surround.txt"[..], b"surround"]
+        );
+        assert_eq!(
+            tags_in_line(b"                                                *ds* *cs*"),
+            vec![&b"ds"[..], b"cs"]
+        );
+        assert!(tags_in_line(b"a*b* *no tag* *c*d **").is_empty());
+    }
+
+    #[test]
+    fn test_generate() {
+        let dir = std::env::temp_dir().join("strand-test-helptags");
+        let _ = std::fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        fs::write(
+            dir.join("surround.txt"),
+            "*surround.txt*  Plugin for deleting parentheses\n\n*ys* *a/b\\c*\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("surround.dex"),
+            "*surround.txt*  Klammern löschen\n",
+        )
+        .unwrap();
+        fs::write(dir.join("README"), "*not-help*\n").unwrap();
+
+        generate(&dir, &|_| ()).unwrap();
+
+        assert_eq!(
+            fs::read_to_string(dir.join("tags")).unwrap(),
+            "a/b\\c\tsurround.txt\t/*a\\/b\\\\c*\n\
+             surround.txt\tsurround.txt\t/*surround.txt*\n\
+             ys\tsurround.txt\t/*ys*\n"
+        );
+        assert_eq!(
+            fs::read_to_string(dir.join("tags-de")).unwrap(),
+            "!_TAG_FILE_ENCODING\tutf-8\t//\n\
+             surround.txt\tsurround.dex\t/*surround.txt*\n"
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,244 @@
+//! Config files that are spread over several files: the ones named in a config file’s `include:`
+//! list, and an overlay for the machine strand is running on.
+//!
+//! Every file is merged into the ones before it. Keys other than `plugins` are overridden by later
+//! files, while `plugins` lists are added together, with a plugin that appears again replacing its
+//! earlier entry where that entry was. Included files come before the file including them, so the
+//! including file has the last word, and the overlay comes after everything else.
+
+use crate::{expand_path, find_one, parse_config, Config, ConfigFormat, PluginSpec};
+use anyhow::{anyhow, bail, Context, Result};
+use serde_json::{Map, Value};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+type Document = Map<String, Value>;
+
+fn parse_document(text: &str, format: ConfigFormat) -> Result<Value> {
+    let document = match format {
+        ConfigFormat::Yaml => yaml::from_str(text)?,
+        ConfigFormat::Toml => toml::from_str(text)?,
+        ConfigFormat::Json => serde_json::from_str(text)?,
+    };
+
+    Ok(document)
+}
+
+fn merge(base: &mut Document, document: Document) {
+    for (key, value) in document {
+        match (base.get_mut(&key), value) {
+            (Some(Value::Array(plugins)), Value::Array(more)) if key == "plugins" => {
+                plugins.extend(more)
+            }
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+// Includes are relative to the file that includes them, and may be globs. A glob that matches
+// nothing is fine, but a path without any wildcards has to exist.
+fn include_paths(include: &str, dir: &Path) -> Result<Vec<PathBuf>> {
+    let path = expand_path(Path::new(include));
+
+    let pattern = if path.is_absolute() {
+        path.to_string_lossy().into_owned()
+    } else {
+        let dir = glob::Pattern::escape(&dir.to_string_lossy());
+        format!("{}/{}", dir, path.display())
+    };
+
+    let mut paths = glob::glob(&pattern)
+        .with_context(|| format!("included config file ‘{}’ is not a valid glob", include))?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if paths.is_empty() && !include.contains(|c| "*?[".contains(c)) {
+        bail!("included config file {} does not exist", pattern);
+    }
+
+    paths.sort();
+
+    Ok(paths)
+}
+
+// `stack` holds the files that are part of the way through being loaded, to catch cycles.
+fn load(path: &Path, stack: &mut Vec<PathBuf>) -> Result<Document> {
+    let canonical = path
+        .canonicalize()
+        .with_context(|| format!("failed to read config file {}", path.display()))?;
+
+    if stack.contains(&canonical) {
+        bail!("config file {} ends up including itself", path.display());
+    }
+
+    let format = ConfigFormat::from_path(path)?;
+    let text = fs::read_to_string(path)?;
+
+    // Parsing the file on its own first gives errors that point at the right line.
+    let config = parse_config(&text, format)
+        .with_context(|| format!("failed to parse config file {}", path.display()))?;
+
+    let mut document = match parse_document(&text, format)? {
+        Value::Object(document) => document,
+        _ => bail!("config file {} is not a set of keys", path.display()),
+    };
+    document.remove("include");
+
+    let dir = path.parent().unwrap_or_else(|| Path::new("."));
+    let mut merged = Document::new();
+
+    stack.push(canonical);
+
+    for include in &config.include {
+        for path in include_paths(include, dir)? {
+            merge(&mut merged, load(&path, stack)?);
+        }
+    }
+
+    stack.pop();
+    merge(&mut merged, document);
+
+    Ok(merged)
+}
+
+/// The overlay for a machine sits next to the config file, with the machine’s hostname before its
+/// extension, e.g. `config.laptop.yaml`. It can be in any of the formats a config file can.
+fn find_overlay(config_file: &Path, hostname: &str) -> Result<Option<PathBuf>> {
+    let dir = config_file.parent().unwrap_or_else(|| Path::new("."));
+    let stem = config_file
+        .file_stem()
+        .ok_or_else(|| anyhow!("config file {} has no name", config_file.display()))?;
+
+    let names: Vec<_> = ["yaml", "toml", "json"]
+        .iter()
+        .map(|extension| format!("{}.{}.{}", stem.to_string_lossy(), hostname, extension))
+        .collect();
+
+    find_one(dir, &names)
+}
+
+/// Loads a config file along with everything it includes, and the overlay for the given machine.
+pub(crate) fn load_config(config_file: &Path, hostname: Option<&str>) -> Result<Config> {
+    let mut document = load(config_file, &mut Vec::new())?;
+
+    if let Some(hostname) = hostname {
+        if let Some(overlay) = find_overlay(config_file, hostname)? {
+            merge(&mut document, load(&overlay, &mut Vec::new())?);
+        }
+    }
+
+    let mut config: Config = serde_json::from_value(Value::Object(document))?;
+    let mut plugins: Vec<PluginSpec> = Vec::with_capacity(config.plugins.len());
+
+    for plugin in config.plugins {
+        let id = plugin.source.id();
+
+        match plugins.iter().position(|other| other.source.id() == id) {
+            Some(i) => plugins[i] = plugin,
+            None => plugins.push(plugin),
+        }
+    }
+
+    config.plugins = plugins;
+
+    Ok(config)
+}
+
+/// This machine’s hostname, up to its first dot, e.g. `laptop` for `laptop.local`.
+pub(crate) fn hostname() -> Option<String> {
+    let hostname = hostname::get().ok()?;
+    let hostname = hostname.to_str()?;
+
+    hostname.split('.').next().map(String::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_config() {
+        let dir = std::env::temp_dir().join("strand-test-include");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("conf.d")).unwrap();
+
+        let write = |name: &str, text: &str| fs::write(dir.join(name), text).unwrap();
+
+        write(
+            "config.yaml",
+            "\
+pack_dir: ~/.vim/pack/strand
+include:
+  - common.toml
+  - conf.d/*.json
+plugins:
+  - Git: tpope/vim-repeat
+  - Git: junegunn/fzf
+    run: ./install --all
+",
+        );
+        write(
+            "common.toml",
+            r#"
+pack_dir = "/tmp/common"
+run_timeout = 600
+retries = 1
+
+[[plugins]]
+Git = "tpope/vim-surround"
+
+[[plugins]]
+Git = "junegunn/fzf"
+run = "./install --bin"
+"#,
+        );
+        write(
+            "conf.d/b.json",
+            r#"{ "plugins": [{ "Git": "tpope/vim-endwise" }] }"#,
+        );
+        write(
+            "conf.d/a.json",
+            r#"{ "plugins": [{ "Git": "tpope/vim-abolish" }] }"#,
+        );
+        write(
+            "config.laptop.yaml",
+            "retries: 5\nplugins:\n  - Git: dstein64/vim-startuptime\n",
+        );
+
+        let config = load_config(&dir.join("config.yaml"), Some("laptop")).unwrap();
+        let ids: Vec<_> = config.plugins.iter().map(|p| p.source.id()).collect();
+
+        assert_eq!(
+            ids,
+            [
+                "github@tpope/vim-surround",
+                "github@junegunn/fzf",
+                "github@tpope/vim-abolish",
+                "github@tpope/vim-endwise",
+                "github@tpope/vim-repeat",
+                "github@dstein64/vim-startuptime",
+            ]
+        );
+        assert_eq!(config.plugins[1].run.as_deref(), Some("./install --all"));
+        assert_eq!(config.pack_dir, Path::new("~/.vim/pack/strand"));
+        assert_eq!(config.run_timeout, 600);
+        assert_eq!(config.retries, 5);
+
+        let config = load_config(&dir.join("config.yaml"), Some("desktop")).unwrap();
+        assert_eq!(config.plugins.len(), 5);
+        assert_eq!(config.retries, 1);
+
+        write("common.toml", "include = [\"config.yaml\"]\n");
+        let error = load_config(&dir.join("config.yaml"), None).err().unwrap();
+        assert!(error.to_string().contains("including itself"));
+
+        write("common.toml", "include = [\"missing.yaml\"]\n");
+        let error = load_config(&dir.join("config.yaml"), None).err().unwrap();
+        assert!(error.to_string().contains("does not exist"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,4 +1,4 @@
-use anyhow::Result;
+use anyhow::{anyhow, bail, Result};
 use async_std::task;
 use serde::Deserialize;
 use std::{
@@ -6,10 +6,56 @@ use std::{
     fmt,
     path::{Path, PathBuf},
     str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 use thiserror::Error;
 use url::Url;
 
+mod archive;
+mod build;
+mod cache;
+mod changes;
+mod clone;
+mod download;
+mod edit;
+mod generations;
+mod helptags;
+mod include;
+mod limit;
+mod list;
+mod lock;
+mod outdated;
+mod progress;
+mod remote;
+mod report;
+mod staging;
+mod state;
+#[cfg(test)]
+mod test_server;
+
+pub use archive::Format;
+pub use cache::OfflineError;
+pub use changes::{changes, print_changes, Change, PluginChanges, Update};
+use download::Download;
+pub use download::DownloadOptions;
+pub use edit::{add_plugins, remove_plugin, write_config, PluginArg};
+pub use generations::{
+    list as list_generations, print_generations, rollback, Generation, GenerationPlugin,
+    GenerationStamp,
+};
+pub use list::{list, print_list, ListEntry, ListStatus};
+pub use lock::{LockedPlugin, Lockfile};
+pub use outdated::{outdated, print_outdated, OutdatedEntry, OutdatedStatus};
+pub use progress::ProgressStyle;
+use progress::Stage;
+pub use report::{Outcome, PluginReport, Report};
+pub use staging::Staging;
+pub use state::{InstalledPlugin, State};
+
 fn get_home_dir() -> PathBuf {
     use std::process;
 
@@ -41,6 +87,25 @@ pub fn get_config_dir() -> PathBuf {
     dir.join("strand")
 }
 
+pub fn get_cache_dir() -> PathBuf {
+    #[cfg(target_os = "macos")]
+    let dir = match std::env::var_os("XDG_CACHE_HOME") {
+        Some(dir) => PathBuf::from(dir),
+        None => get_home_dir().join(".cache"),
+    };
+
+    #[cfg(not(target_os = "macos"))]
+    let dir = match dirs::cache_dir() {
+        Some(dir) => dir,
+        None => {
+            eprintln!("Error: could not locate cache directory -- exiting.");
+            std::process::exit(1);
+        }
+    };
+
+    dir.join("strand")
+}
+
 fn expand_path(path: &Path) -> PathBuf {
     use std::path::Component;
 
@@ -62,19 +127,52 @@ fn expand_path(path: &Path) -> PathBuf {
     }
 }
 
-#[derive(Deserialize)]
+#[derive(Clone, Deserialize)]
 pub enum GitProvider {
     GitHub,
     GitLab,
     Bitbucket,
+    Gitea,
+    Forgejo,
+    Codeberg,
+    Sourcehut,
 }
 
 #[derive(Error, Debug)]
 pub enum GitProviderParseError {
-    #[error("Git provider {0} not recognised -- try ‘github’, ‘gitlab’ or ‘bitbucket’ instead")]
+    #[error("Git provider {0} not recognised -- try ‘github’, ‘gitlab’, ‘bitbucket’, ‘gitea’, ‘forgejo’, ‘codeberg’ or ‘sourcehut’ instead")]
     UnknownProvider(String),
 }
 
+impl GitProvider {
+    // Forgejo is only ever self-hosted, so it has no host to fall back on.
+    fn default_host(&self) -> Option<&'static str> {
+        match self {
+            GitProvider::GitHub => Some("github.com"),
+            GitProvider::GitLab => Some("gitlab.com"),
+            GitProvider::Bitbucket => Some("bitbucket.org"),
+            GitProvider::Gitea => Some("gitea.com"),
+            GitProvider::Forgejo => None,
+            GitProvider::Codeberg => Some("codeberg.org"),
+            GitProvider::Sourcehut => Some("git.sr.ht"),
+        }
+    }
+}
+
+impl fmt::Display for GitProvider {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GitProvider::GitHub => write!(f, "github"),
+            GitProvider::GitLab => write!(f, "gitlab"),
+            GitProvider::Bitbucket => write!(f, "bitbucket"),
+            GitProvider::Gitea => write!(f, "gitea"),
+            GitProvider::Forgejo => write!(f, "forgejo"),
+            GitProvider::Codeberg => write!(f, "codeberg"),
+            GitProvider::Sourcehut => write!(f, "sourcehut"),
+        }
+    }
+}
+
 impl FromStr for GitProvider {
     type Err = GitProviderParseError;
 
@@ -83,47 +181,141 @@ impl FromStr for GitProvider {
             "github" => Ok(GitProvider::GitHub),
             "gitlab" => Ok(GitProvider::GitLab),
             "bitbucket" => Ok(GitProvider::Bitbucket),
+            "gitea" => Ok(GitProvider::Gitea),
+            "forgejo" => Ok(GitProvider::Forgejo),
+            "codeberg" => Ok(GitProvider::Codeberg),
+            "sourcehut" | "srht" => Ok(GitProvider::Sourcehut),
             _ => Err(Self::Err::UnknownProvider(s.into())),
         }
     }
 }
 
-// git_ref can be a branch name, tag name, or commit hash.
-#[derive(Deserialize)]
+// git_ref can be a branch name, tag name, or commit hash. When it is elided the repo’s default
+// branch is used. host is only set for self-hosted instances, e.g. GitHub Enterprise or a company’s
+// own GitLab.
+#[derive(Clone, Deserialize)]
 #[serde(try_from = "String")]
 pub struct GitRepo {
     provider: GitProvider,
+    host: Option<String>,
     user: String,
     repo: String,
-    git_ref: String,
+    git_ref: Option<String>,
 }
 
-impl fmt::Display for GitRepo {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+impl GitRepo {
+    /// A stable identifier for the repo that includes its host and Git reference if they were
+    /// given, e.g. `github@tpope/vim-surround:master` or `gitlab(git.corp.example)@team/vim-foo`.
+    fn id(&self) -> String {
+        let id = match &self.host {
+            Some(host) => format!("{}({})@{}/{}", self.provider, host, self.user, self.repo),
+            None => format!("{}@{}/{}", self.provider, self.user, self.repo),
+        };
+
+        match &self.git_ref {
+            Some(git_ref) => format!("{}:{}", id, git_ref),
+            None => id,
+        }
+    }
+
+    /// The URL of the host the repo lives on. Hosts are assumed to use HTTPS unless they say
+    /// otherwise, e.g. `gitea(http://git.local)`.
+    fn base_url(&self) -> String {
+        let host = self
+            .host
+            .as_deref()
+            .or_else(|| self.provider.default_host())
+            .unwrap_or_default();
+
+        if host.contains("://") {
+            host.trim_end_matches('/').into()
+        } else {
+            format!("https://{}", host)
+        }
+    }
+
+    // sourcehut usernames start with a ‘~’, which may be left out.
+    fn sourcehut_user(&self) -> String {
+        format!("~{}", self.user.trim_start_matches('~'))
+    }
+
+    /// The URL the repo can be cloned from, which is also where the smart HTTP protocol lives.
+    fn repo_url(&self) -> String {
+        let base = self.base_url();
+
         match self.provider {
-            GitProvider::GitHub => write!(
-                f,
+            // Bitbucket Server and Data Center lay out their repos differently to bitbucket.org.
+            GitProvider::Bitbucket if self.host.is_some() => {
+                format!("{}/scm/{}/{}.git", base, self.user, self.repo)
+            }
+            GitProvider::Sourcehut => format!("{}/{}/{}", base, self.sourcehut_user(), self.repo),
+            _ => format!("{}/{}/{}.git", base, self.user, self.repo),
+        }
+    }
+
+    fn archive_url(&self, git_ref: &str) -> String {
+        let base = self.base_url();
+        let (user, repo) = (&self.user, &self.repo);
+
+        match self.provider {
+            GitProvider::GitHub if self.host.is_none() => format!(
                 "https://codeload.github.com/{}/{}/tar.gz/{}",
-                self.user, self.repo, self.git_ref
+                user, repo, git_ref
             ),
-            GitProvider::GitLab => write!(
-                f,
-                "https://gitlab.com/{0}/{1}/-/archive/{2}/{0}-{2}.tar.gz",
-                self.user, self.repo, self.git_ref
+            GitProvider::GitLab => format!(
+                "{0}/{1}/{2}/-/archive/{3}/{1}-{3}.tar.gz",
+                base, user, repo, git_ref
+            ),
+            GitProvider::Bitbucket if self.host.is_none() => {
+                format!("{}/{}/{}/get/{}.tar.gz", base, user, repo, git_ref)
+            }
+            GitProvider::Bitbucket => format!(
+                "{}/rest/api/latest/projects/{}/repos/{}/archive?at={}&format=tar.gz",
+                base, user, repo, git_ref
             ),
-            GitProvider::Bitbucket => write!(
-                f,
-                "https://bitbucket.org/{}/{}/get/{}.tar.gz",
-                self.user, self.repo, self.git_ref
+            GitProvider::Sourcehut => format!(
+                "{}/{}/{}/archive/{}.tar.gz",
+                base,
+                self.sourcehut_user(),
+                repo,
+                git_ref
             ),
+            // GitHub Enterprise, Gitea, Forgejo and Codeberg all share this layout.
+            GitProvider::GitHub
+            | GitProvider::Gitea
+            | GitProvider::Forgejo
+            | GitProvider::Codeberg => {
+                format!("{}/{}/{}/archive/{}.tar.gz", base, user, repo, git_ref)
+            }
         }
     }
+
+    /// Resolves the repo’s Git reference, or its default branch if the reference was elided, to a
+    /// commit hash. Returns the reference that was used along with the commit.
+    async fn resolve(&self, options: &DownloadOptions) -> Result<(String, String)> {
+        remote::resolve(&self.repo_url(), self.git_ref.as_deref(), options).await
+    }
+}
+
+impl fmt::Display for GitRepo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Git hosts’ archive endpoints understand ‘HEAD’ as the default branch.
+        write!(
+            f,
+            "{}",
+            self.archive_url(self.git_ref.as_deref().unwrap_or("HEAD"))
+        )
+    }
 }
 
 #[derive(Error, Debug)]
 pub enum GitRepoParseError {
     #[error("no user was found")]
     MissingUser,
+    #[error("{0} needs to be told which host to use, e.g. ‘{0}(git.example.com)@user/repo’")]
+    MissingHost(String),
+    #[error("malformed host in ‘{0}’ -- it should look like ‘gitlab(git.example.com)’")]
+    MalformedHost(String),
     #[error("failed to parse Git provider")]
     ProviderParse(#[from] GitProviderParseError),
 }
@@ -147,29 +339,39 @@ impl FromStr for GitRepo {
         // been parsed.
         let mut i = 0;
 
-        // Default to GitHub when the provider is elided.
-        let provider = split_on_pattern(&input, "@", &mut i)
-            .map_or(Ok(GitProvider::GitHub), GitProvider::from_str)?;
+        // Default to GitHub when the provider is elided. Self-hosted instances put their host in
+        // brackets after the provider, e.g. ‘gitlab(git.corp.example)’.
+        let (provider, host) = match split_on_pattern(input, "@", &mut i) {
+            Some(provider) => match provider.split_once('(') {
+                Some((provider, host)) => match host.strip_suffix(')') {
+                    Some(host) if !host.is_empty() => (provider.parse()?, Some(host.into())),
+                    _ => return Err(Self::Err::MalformedHost(input[..i - 1].into())),
+                },
+                None => (provider.parse()?, None),
+            },
+            None => (GitProvider::GitHub, None),
+        };
+
+        if host.is_none() && provider.default_host().is_none() {
+            return Err(Self::Err::MissingHost(provider.to_string()));
+        }
 
-        let user =
-            split_on_pattern(&input[i..], "/", &mut i).ok_or_else(|| Self::Err::MissingUser)?;
+        let user = split_on_pattern(&input[i..], "/", &mut i).ok_or(Self::Err::MissingUser)?;
 
         // When the ‘:’ signifier for a Git reference is found, the part preceding it must be the
         // repo name and the part after the Git reference. If it is not found, the rest of ‘input’
-        // must be the repo name, in this case using ‘master’ as the default Git reference.
-        //
-        // FIXME: Some repos have something different to ‘master’ as their default branch. Handle
-        // this somehow.
+        // must be the repo name, and the repo’s default branch is looked up at install time.
         let (repo, git_ref) = match split_on_pattern(&input[i..], ":", &mut i) {
-            Some(repo) => (repo, &input[i..]),
-            None => (&input[i..], "master"),
+            Some(repo) => (repo, Some(&input[i..])),
+            None => (&input[i..], None),
         };
 
         Ok(Self {
             provider,
+            host,
             user: user.into(),
             repo: repo.into(),
-            git_ref: git_ref.into(),
+            git_ref: git_ref.map(String::from),
         })
     }
 }
@@ -182,7 +384,7 @@ impl TryFrom<String> for GitRepo {
     }
 }
 
-#[derive(Deserialize)]
+#[derive(Clone, Deserialize)]
 pub struct ArchivePlugin(Url);
 
 impl fmt::Display for ArchivePlugin {
@@ -191,6 +393,29 @@ impl fmt::Display for ArchivePlugin {
     }
 }
 
+// File extensions that are stripped from the end of archive URLs to name the plugin.
+const ARCHIVE_EXTENSIONS: &[&str] = &[
+    ".tar.gz", ".tgz", ".tar.xz", ".txz", ".tar.bz2", ".tbz2", ".tar.zst", ".tzst", ".tar", ".zip",
+];
+
+impl ArchivePlugin {
+    /// Archives are named after the last segment of their URL’s path minus any file extension,
+    /// e.g. `https://example.com/vim-qlist.tar.gz` is named ‘vim-qlist’.
+    fn name(&self) -> String {
+        let segment = self
+            .0
+            .path_segments()
+            .and_then(|segments| segments.rev().find(|s| !s.is_empty()))
+            .unwrap_or_default();
+
+        ARCHIVE_EXTENSIONS
+            .iter()
+            .find_map(|extension| segment.strip_suffix(extension))
+            .unwrap_or(segment)
+            .into()
+    }
+}
+
 impl FromStr for ArchivePlugin {
     type Err = url::ParseError;
 
@@ -199,9 +424,85 @@ impl FromStr for ArchivePlugin {
     }
 }
 
-#[derive(Deserialize)]
+// A Git repo on a server without an archive endpoint, e.g. a plain SSH server, which is fetched with
+// the `git` binary instead. It is given as the URL it is cloned from, optionally followed by a ‘#’
+// and a Git reference, e.g. `ssh://git@git.corp.example/vim-foo.git#v1.2`.
+#[derive(Clone, Deserialize)]
+#[serde(try_from = "String")]
+pub struct GitCloneRepo {
+    url: String,
+    git_ref: Option<String>,
+}
+
+impl GitCloneRepo {
+    fn id(&self) -> String {
+        match &self.git_ref {
+            Some(git_ref) => format!("{}#{}", self.url, git_ref),
+            None => self.url.clone(),
+        }
+    }
+
+    /// Repos are named after the last segment of their URL minus any `.git`, e.g.
+    /// `git@git.corp.example:vim-foo.git` is named ‘vim-foo’.
+    fn name(&self) -> String {
+        let segment = self
+            .url
+            .trim_end_matches('/')
+            .rsplit(['/', ':'])
+            .next()
+            .unwrap_or_default();
+
+        segment.strip_suffix(".git").unwrap_or(segment).into()
+    }
+}
+
+impl fmt::Display for GitCloneRepo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.id())
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum GitCloneRepoParseError {
+    #[error("no URL was found")]
+    MissingUrl,
+    #[error("empty Git reference after ‘#’ in {0}")]
+    EmptyRef(String),
+}
+
+impl FromStr for GitCloneRepo {
+    type Err = GitCloneRepoParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (url, git_ref) = match s.rsplit_once('#') {
+            Some((_, "")) => return Err(Self::Err::EmptyRef(s.into())),
+            Some((url, git_ref)) => (url, Some(git_ref.into())),
+            None => (s, None),
+        };
+
+        if url.is_empty() {
+            return Err(Self::Err::MissingUrl);
+        }
+
+        Ok(Self {
+            url: url.into(),
+            git_ref,
+        })
+    }
+}
+
+impl TryFrom<String> for GitCloneRepo {
+    type Error = GitCloneRepoParseError;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        Self::from_str(&s)
+    }
+}
+
+#[derive(Clone, Deserialize)]
 pub enum Plugin {
     Git(GitRepo),
+    GitClone(GitCloneRepo),
     Archive(ArchivePlugin),
 }
 
@@ -209,6 +510,7 @@ impl fmt::Display for Plugin {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Plugin::Git(plugin) => write!(f, "{}", plugin),
+            Plugin::GitClone(plugin) => write!(f, "{}", plugin),
             Plugin::Archive(plugin) => write!(f, "{}", plugin),
         }
     }
@@ -218,6 +520,8 @@ impl fmt::Display for Plugin {
 pub enum PluginParseError {
     #[error("failed to parse Git repo: {0}")]
     GitParse(#[from] GitRepoParseError),
+    #[error("failed to parse Git clone URL: {0}")]
+    GitCloneParse(#[from] GitCloneRepoParseError),
     #[error("failed to parse archive plugin: {0}")]
     ArchiveParse(#[from] url::ParseError),
 }
@@ -225,75 +529,887 @@ pub enum PluginParseError {
 impl FromStr for Plugin {
     type Err = PluginParseError;
 
+    // URLs are taken to be archives unless they look like something only Git can fetch.
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        ArchivePlugin::from_str(s)
-            .map(Plugin::Archive)
-            .or_else(|_| GitRepo::from_str(s).map(Plugin::Git).map_err(|e| e.into()))
+        match Url::from_str(s) {
+            Ok(url)
+                if matches!(url.scheme(), "ssh" | "git" | "file")
+                    || url.path().ends_with(".git") =>
+            {
+                Ok(Plugin::GitClone(s.parse()?))
+            }
+            Ok(url) => Ok(Plugin::Archive(ArchivePlugin(url))),
+            Err(_) => Ok(Plugin::Git(s.parse()?)),
+        }
+    }
+}
+
+// Works out which commit of a Git repo to install: the lockfile’s if there is one, and otherwise
+// whatever `resolve` says its reference points to. Offline, only references that are already commit
+// hashes can be used without a lockfile entry.
+async fn resolve_commit(
+    id: &str,
+    git_ref: Option<&String>,
+    pin: Option<&LockedPlugin>,
+    offline: bool,
+    resolve: impl std::future::Future<Output = Result<(String, String)>>,
+) -> Result<(Option<String>, String)> {
+    match pin {
+        Some(pin) => {
+            let commit = pin
+                .commit
+                .clone()
+                .ok_or_else(|| anyhow!("lockfile does not record a commit for {}", id))?;
+
+            Ok((git_ref.cloned(), commit))
+        }
+        None if offline => match git_ref {
+            Some(git_ref) if remote::is_commit_hash(git_ref) => {
+                Ok((Some(git_ref.clone()), git_ref.clone()))
+            }
+            _ => Err(OfflineError::Unresolved.into()),
+        },
+        None => {
+            let (git_ref, commit) = resolve.await?;
+            Ok((Some(git_ref), commit))
+        }
     }
 }
 
 impl Plugin {
-    async fn install_plugin(&self, path: PathBuf) -> Result<()> {
+    /// A stable identifier for the plugin, used to key it in the lockfile.
+    pub fn id(&self) -> String {
+        match self {
+            Plugin::Git(repo) => repo.id(),
+            Plugin::GitClone(repo) => repo.id(),
+            Plugin::Archive(archive) => archive.to_string(),
+        }
+    }
+
+    /// The key the plugin is given under in the config file.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Plugin::Git(_) => "Git",
+            Plugin::GitClone(_) => "GitClone",
+            Plugin::Archive(_) => "Archive",
+        }
+    }
+
+    /// Works out where to download the plugin’s archive from. Git references are resolved to a
+    /// commit, unless a lockfile entry is given in which case its commit is used. Offline, only
+    /// references that are already commit hashes can be resolved without one.
+    async fn resolve(
+        &self,
+        pin: Option<&LockedPlugin>,
+        offline: bool,
+        options: &DownloadOptions,
+    ) -> Result<ResolvedPlugin> {
+        match self {
+            Plugin::Git(repo) => {
+                let resolve = repo.resolve(options);
+                let (git_ref, commit) =
+                    resolve_commit(&repo.id(), repo.git_ref.as_ref(), pin, offline, resolve)
+                        .await?;
+
+                Ok(ResolvedPlugin {
+                    url: repo.archive_url(&commit),
+                    git_ref,
+                    commit: Some(commit),
+                })
+            }
+            // There is no archive to download, so the URL only serves to record what was installed.
+            Plugin::GitClone(repo) => {
+                let resolve = clone::resolve(&repo.url, repo.git_ref.as_deref(), options.timeout);
+                let (git_ref, commit) =
+                    resolve_commit(&repo.id(), repo.git_ref.as_ref(), pin, offline, resolve)
+                        .await?;
+
+                Ok(ResolvedPlugin {
+                    url: format!("{}#{}", repo.url, commit),
+                    git_ref,
+                    commit: Some(commit),
+                })
+            }
+            Plugin::Archive(archive) => Ok(ResolvedPlugin {
+                url: archive.to_string(),
+                git_ref: None,
+                commit: None,
+            }),
+        }
+    }
+
+    /// Git repos are named after the repo, and archives after their URL.
+    fn default_name(&self) -> String {
+        match self {
+            Plugin::Git(repo) => repo.repo.clone(),
+            Plugin::GitClone(repo) => repo.name(),
+            Plugin::Archive(archive) => archive.name(),
+        }
+    }
+
+    /// The host the plugin is downloaded from, so that downloads can be spread between hosts.
+    fn host(&self) -> String {
+        match self {
+            Plugin::Git(repo) => url_host(&repo.base_url()),
+            Plugin::GitClone(repo) => url_host(&repo.url),
+            Plugin::Archive(archive) => archive.0.host_str().unwrap_or_default().into(),
+        }
+    }
+
+    // Repos without an archive endpoint are exported to a tar archive of our own instead.
+    async fn download(
+        &self,
+        resolved: &ResolvedPlugin,
+        options: &DownloadOptions,
+        on_progress: download::OnProgress<'_>,
+    ) -> Result<Download> {
+        match (self, &resolved.commit) {
+            (Plugin::GitClone(repo), Some(commit)) => {
+                let git_ref = resolved.git_ref.as_deref();
+                let body = clone::export(&repo.url, git_ref, commit, options.timeout).await?;
+
+                Ok(Download {
+                    body,
+                    content_type: None,
+                })
+            }
+            _ => download::get(&resolved.url, options, on_progress).await,
+        }
+    }
+}
+
+// Git also understands scp-like addresses such as `git@git.corp.example:vim-foo.git`, which are not
+// URLs.
+fn url_host(url: &str) -> String {
+    match Url::from_str(url) {
+        Ok(url) => url.host_str().unwrap_or_default().into(),
+        Err(_) => {
+            let address = url.split(':').next().unwrap_or_default();
+            address.rsplit('@').next().unwrap_or_default().into()
+        }
+    }
+}
+
+struct ResolvedPlugin {
+    url: String,
+    git_ref: Option<String>,
+    commit: Option<String>,
+}
+
+/// Downloads a plugin’s archive without installing it, e.g. to find out its hash.
+pub async fn fetch_archive(plugin: &Plugin, options: &DownloadOptions) -> Result<Vec<u8>> {
+    let resolved = plugin.resolve(None, false, options).await?;
+
+    Ok(plugin.download(&resolved, options, &|_, _| ()).await?.body)
+}
+
+/// A plugin as specified in the config file, along with the options it was given there, e.g.
+///
+/// ```yaml
+/// - Git: junegunn/fzf
+///   as: fzf-vim
+///   run: ./install --bin
+///   opt: true
+/// - Archive: https://example.com/vim-qlist.tar.gz
+///   sha256: 8f434346648f6b96df89dda901c5176b10a6d83961dd3c1ac88b59b2dc327aa4
+/// ```
+#[derive(Deserialize)]
+pub struct PluginSpec {
+    #[serde(flatten)]
+    pub source: Plugin,
+    /// The name of the directory to install the plugin into, overriding the default.
+    #[serde(default, rename = "as")]
+    pub name: Option<String>,
+    /// A shell command that builds the plugin, run inside its directory after it is unpacked.
+    #[serde(default)]
+    pub run: Option<String>,
+    /// Whether the plugin is installed as an optional package to be loaded with `:packadd`.
+    #[serde(default)]
+    pub opt: bool,
+    /// The expected SHA-256 hash of the plugin’s archive, as printed by `strand hash`.
+    #[serde(default)]
+    pub sha256: Option<String>,
+    /// The expected SHA-512 hash of the plugin’s archive, as printed by `strand hash --sha512`.
+    #[serde(default)]
+    pub sha512: Option<String>,
+    /// The kind of archive the plugin is downloaded as, for when it cannot be detected.
+    #[serde(default)]
+    pub format: Option<Format>,
+}
+
+impl From<Plugin> for PluginSpec {
+    fn from(source: Plugin) -> Self {
+        Self {
+            source,
+            name: None,
+            run: None,
+            opt: false,
+            sha256: None,
+            sha512: None,
+            format: None,
+        }
+    }
+}
+
+impl PluginSpec {
+    /// The name of the directory that the plugin is installed into.
+    pub fn dir_name(&self) -> String {
+        self.name
+            .clone()
+            .unwrap_or_else(|| self.source.default_name())
+    }
+
+    /// Where the plugin is installed relative to the pack directory, e.g. `start/vim-surround`.
+    pub fn install_dir(&self) -> PathBuf {
+        let kind = if self.opt { "opt" } else { "start" };
+        Path::new(kind).join(self.dir_name())
+    }
+
+    // Makes sure that a downloaded archive is what the config file says it should be.
+    fn check_integrity(&self, archive: &[u8], url: &str) -> Result<()> {
+        let expected_hashes = [
+            ("SHA-256", &self.sha256, sha256_hex as fn(&[u8]) -> String),
+            ("SHA-512", &self.sha512, sha512_hex),
+        ];
+
+        for (algorithm, expected, hash) in &expected_hashes {
+            if let Some(expected) = expected {
+                let actual = hash(archive);
+
+                if !actual.eq_ignore_ascii_case(expected.trim()) {
+                    bail!(
+                        "{} of archive downloaded from {} is {} but the config file expects {}",
+                        algorithm,
+                        url,
+                        actual,
+                        expected
+                    );
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn format_of(&self, archive: &[u8], content_type: Option<&str>, url: &str) -> Result<Format> {
+        self.format
+            .or_else(|| Format::detect(archive, content_type))
+            .ok_or_else(|| {
+                anyhow!(
+                    "could not tell what kind of archive was downloaded from {} -- use ‘format:’ to say",
+                    url
+                )
+            })
+    }
+
+    // Everything a download has to get past before it is unpacked, or cached.
+    fn check_download(
+        &self,
+        download: &Download,
+        pin: Option<&LockedPlugin>,
+        url: &str,
+    ) -> Result<()> {
+        self.check_integrity(&download.body, url)?;
+
+        // Git plugins are already pinned by their commit, so only archives need a content hash.
+        if let (Plugin::Archive(_), Some(pin)) = (&self.source, pin) {
+            if pin.sha256.as_deref() != Some(&sha256_hex(&download.body)) {
+                bail!(
+                    "archive downloaded from {} does not match the hash recorded in the lockfile",
+                    url
+                );
+            }
+        }
+
+        self.format_of(&download.body, download.content_type.as_deref(), url)?;
+
+        Ok(())
+    }
+
+    // A Git archive at a given commit never changes, so it is cached under its URL. Other archives
+    // are cached under the hash they are expected to have, since nothing stops the archive at a
+    // URL from changing, or the config file from expecting a different one. Without either they
+    // are not cached at all.
+    fn cache_key(&self, resolved: &ResolvedPlugin, pin: Option<&LockedPlugin>) -> Option<String> {
+        let hash = |algorithm, hash: &String| {
+            format!("{}:{}", algorithm, hash.trim().to_ascii_lowercase())
+        };
+
+        match &self.source {
+            Plugin::Git(_) | Plugin::GitClone(_) => {
+                resolved.commit.as_ref().map(|_| resolved.url.clone())
+            }
+            Plugin::Archive(_) => self
+                .sha512
+                .as_ref()
+                .map(|sha512| hash("sha512", sha512))
+                .or_else(|| {
+                    let sha256 = self.sha256.as_ref();
+                    let sha256 = sha256.or_else(|| pin.and_then(|pin| pin.sha256.as_ref()));
+                    sha256.map(|sha256| hash("sha256", sha256))
+                }),
+        }
+    }
+
+    // When a lockfile entry is given the plugin is installed exactly as it describes; otherwise
+    // Git references are resolved afresh. If that matches what is already installed nothing is
+    // downloaded. Either way, what is now installed is returned so that it can be recorded.
+    // Newly-installed plugins are built with their build command, if they have one.
+    async fn install_plugin(
+        &self,
+        pack_dir: PathBuf,
+        pin: Option<LockedPlugin>,
+        installed: Option<InstalledPlugin>,
+        options: InstallOptions,
+        permit: limit::Permit,
+        progress: progress::Progress,
+    ) -> Result<InstalledPlugin> {
         use anyhow::Context;
-        use std::process;
 
-        let url = format!("{}", self);
-        let archive = match surf::get(url).recv_bytes().await {
-            Ok(response) => response,
-            Err(e) => {
-                eprintln!("Error: {}", e);
-                process::exit(1);
+        let line = progress.start(self.source.id());
+
+        let resolved = self
+            .source
+            .resolve(pin.as_ref(), options.offline, &options.download)
+            .await?;
+        let (url, commit) = (&resolved.url, &resolved.commit);
+
+        // Archive URLs are assumed not to change what they point to unless the lockfile or config
+        // file say otherwise, since the only way to find out is to download them.
+        if let Some(installed) = &installed {
+            let up_to_date = match &self.source {
+                Plugin::Git(_) | Plugin::GitClone(_) => &installed.version.commit == commit,
+                Plugin::Archive(_) => {
+                    let matches = |expected: Option<&String>, hash: &Option<String>| {
+                        expected.is_none_or(|expected| {
+                            let expected = expected.trim();
+                            hash.as_ref()
+                                .is_some_and(|hash| hash.eq_ignore_ascii_case(expected))
+                        })
+                    };
+
+                    let sha256 = pin.as_ref().and_then(|pin| pin.sha256.as_ref());
+                    let sha256 = sha256.or(self.sha256.as_ref());
+
+                    matches(sha256, &installed.version.sha256)
+                        && matches(self.sha512.as_ref(), &installed.version.sha512)
+                }
+            };
+
+            // A changed build command means the plugin needs to be built again, and a changed
+            // format that it needs to be unpacked again.
+            if up_to_date
+                && installed.run == self.run
+                && installed.format == self.format
+                && installed.dir == self.install_dir()
+                && installed.is_present(&pack_dir)
+            {
+                return Ok(installed.clone());
             }
+        }
+
+        let download = fetch(
+            &self.source,
+            &resolved,
+            self.cache_key(&resolved, pin.as_ref()).as_deref(),
+            &options,
+            &line,
+            |download| self.check_download(download, pin.as_ref(), url),
+        )
+        .await?;
+        let archive = download.body;
+
+        // Only talking to servers is limited; plugins can be unpacked and built all at once.
+        drop(permit);
+        line.set(Stage::Extracting);
+
+        let (sha256, sha512) = match &self.source {
+            Plugin::Git(_) | Plugin::GitClone(_) => (None, None),
+            Plugin::Archive(_) => (
+                Some(sha256_hex(&archive)),
+                self.sha512.as_ref().map(|_| sha512_hex(&archive)),
+            ),
         };
-        decompress_tar_gz(&archive, &path).with_context(|| {
-            format!(
-                "failed to extact archive while installing plugin from URL {} -- got from server:\n‘{}’",
-                self, String::from_utf8_lossy(&archive)
-            )
-        })?;
-        println!("Installed {}", self);
 
-        Ok(())
+        // Only remove the old version once we know we have a new one to replace it with.
+        if let Some(installed) = &installed {
+            installed.remove(&pack_dir).await?;
+        }
+
+        let format = self.format_of(&archive, download.content_type.as_deref(), url)?;
+
+        let dir = self.install_dir();
+        let path = pack_dir.join(&dir);
+        unpack_plugin(&archive, format, &path)
+            .await
+            .with_context(|| format!("failed to extract archive downloaded from {}", url))?;
+
+        if let Some(run) = &self.run {
+            line.set(Stage::Building);
+            build::run(run, &path, options.run_timeout)
+                .await
+                .with_context(|| format!("failed to build {}", self.source))?;
+        }
+
+        let doc_dir = path.join("doc");
+
+        if doc_dir.is_dir() {
+            helptags::generate(&doc_dir, &|warning| line.warn(warning))?;
+        }
+
+        line.finish(&format!("Installed {}", self.source));
+
+        Ok(InstalledPlugin {
+            source: resolved.url,
+            git_ref: resolved.git_ref,
+            version: LockedPlugin {
+                commit: resolved.commit,
+                sha256,
+                sha512,
+            },
+            dir,
+            run: self.run.clone(),
+            format: self.format,
+            installed_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|time| time.as_secs())
+                .ok(),
+        })
+    }
+}
+
+/// The languages a config file can be written in, going by its extension.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ConfigFormat {
+    Yaml,
+    Toml,
+    Json,
+}
+
+impl ConfigFormat {
+    pub fn from_path(path: &Path) -> Result<Self> {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("yaml") | Some("yml") => Ok(ConfigFormat::Yaml),
+            Some("toml") => Ok(ConfigFormat::Toml),
+            Some("json") => Ok(ConfigFormat::Json),
+            _ => bail!(
+                "config file {} is not a ‘.yaml’, ‘.toml’ or ‘.json’ file",
+                path.display()
+            ),
+        }
+    }
+}
+
+// In order of preference for `strand --config-location` when there is no config file yet.
+const CONFIG_FILE_NAMES: [&str; 3] = ["config.yaml", "config.toml", "config.json"];
+
+/// Finds the config file in the given directory, whichever format it is in. It is an error for
+/// there to be more than one, since only one of them would be used.
+pub fn find_config_file(config_dir: &Path) -> Result<PathBuf> {
+    let found = find_one(config_dir, &CONFIG_FILE_NAMES)?;
+
+    Ok(found.unwrap_or_else(|| config_dir.join(CONFIG_FILE_NAMES[0])))
+}
+
+// Returns whichever of the given config files exists, if any.
+fn find_one(config_dir: &Path, names: &[impl AsRef<Path>]) -> Result<Option<PathBuf>> {
+    let found: Vec<_> = names
+        .iter()
+        .map(|name| config_dir.join(name))
+        .filter(|path| path.exists())
+        .collect();
+
+    match found.as_slice() {
+        [] => Ok(None),
+        [path] => Ok(Some(path.clone())),
+        _ => {
+            let names: Vec<_> = found
+                .iter()
+                .filter_map(|path| path.file_name())
+                .map(|name| name.to_string_lossy())
+                .collect();
+
+            bail!(
+                "found more than one config file in {} ({}) -- remove all but one",
+                config_dir.display(),
+                names.join(", ")
+            )
+        }
     }
 }
 
 #[derive(Deserialize)]
 pub struct Config {
-    pub plugin_dir: PathBuf,
-    pub plugins: Vec<Plugin>,
+    /// The package plugins are installed into, e.g. `~/.vim/pack/strand`. Plugins go in its
+    /// `start` directory, or its `opt` directory if they are optional.
+    #[serde(default)]
+    pub pack_dir: PathBuf,
+    // Older config files name the `start` directory directly.
+    #[serde(default)]
+    plugin_dir: Option<PathBuf>,
+    /// Other config files to merge into this one, which are relative to it and may be globs.
+    #[serde(default)]
+    include: Vec<String>,
+    #[serde(default)]
+    pub plugins: Vec<PluginSpec>,
+    /// How many seconds a plugin’s build command may run for before it is killed.
+    #[serde(default = "default_run_timeout")]
+    pub run_timeout: u64,
+    /// How many seconds a single download may take before it is given up on.
+    #[serde(default = "default_download_timeout")]
+    pub download_timeout: u64,
+    /// How many times to retry downloads that fail because of a network or server error.
+    #[serde(default = "default_retries")]
+    pub retries: u32,
+    /// How many plugins to download at once.
+    #[serde(default = "default_jobs")]
+    pub jobs: usize,
+    /// How many plugins to download at once from any one host.
+    #[serde(default = "default_jobs_per_host")]
+    pub jobs_per_host: usize,
+    /// How many earlier versions of the pack directory to keep for rolling back to.
+    #[serde(default = "default_generations")]
+    pub generations: usize,
+}
+
+fn default_run_timeout() -> u64 {
+    300
+}
+
+fn default_download_timeout() -> u64 {
+    60
+}
+
+fn default_retries() -> u32 {
+    3
 }
 
+fn default_generations() -> usize {
+    5
+}
+
+fn default_jobs() -> usize {
+    8
+}
+
+fn default_jobs_per_host() -> usize {
+    4
+}
+
+/// Settings that apply to every plugin being installed.
+#[derive(Clone)]
+pub struct InstallOptions {
+    /// How long a plugin’s build command may run for before it is killed.
+    pub run_timeout: Duration,
+    /// Where downloaded archives are cached.
+    pub cache_dir: PathBuf,
+    /// Whether to install purely from the cache, without touching the network.
+    pub offline: bool,
+    /// Whether to give up on the first plugin that fails to install, rather than installing as
+    /// many as possible and reporting every failure.
+    pub fail_fast: bool,
+    /// How many plugins to download at once, overall and from any one host.
+    pub jobs: usize,
+    pub jobs_per_host: usize,
+    /// How to show what each plugin is doing.
+    pub progress: ProgressStyle,
+    pub download: DownloadOptions,
+}
+
+fn parse_config(text: &str, format: ConfigFormat) -> Result<Config> {
+    let config = match format {
+        ConfigFormat::Yaml => yaml::from_str(text)?,
+        ConfigFormat::Toml => toml::from_str(text)?,
+        ConfigFormat::Json => serde_json::from_str(text)?,
+    };
+
+    Ok(config)
+}
+
+/// Loads the config file, along with any files it includes and the overlay for this machine.
 pub async fn get_config(config_file: &Path) -> Result<Config> {
-    use async_std::fs;
+    let mut config = include::load_config(config_file, include::hostname().as_deref())?;
+
+    if let Some(plugin_dir) = config.plugin_dir.take() {
+        if !config.pack_dir.as_os_str().is_empty() {
+            bail!("config file specifies both pack_dir and plugin_dir -- remove plugin_dir");
+        }
+
+        config.pack_dir = match plugin_dir.parent() {
+            Some(pack_dir) if plugin_dir.ends_with("start") => pack_dir.into(),
+            _ => bail!(
+                "plugin_dir {} is not the ‘start’ directory of a package -- use pack_dir instead",
+                plugin_dir.display()
+            ),
+        };
+    }
+
+    if config.pack_dir.as_os_str().is_empty() {
+        bail!("config file does not specify pack_dir");
+    }
 
-    let config = fs::read_to_string(config_file).await?;
-    let mut config: Config = yaml::from_str(&config)?;
-    config.plugin_dir = expand_path(&config.plugin_dir);
+    config.pack_dir = expand_path(&config.pack_dir);
 
     Ok(config)
 }
 
-fn decompress_tar_gz(bytes: &[u8], path: &Path) -> Result<()> {
-    use flate2::read::GzDecoder;
-    use tar::Archive;
+// Downloads an archive, going through the cache if it is safe to do so. Archives are checked with
+// `check` whether they come from the cache or not, and only ones that pass are cached, so that a
+// bad download is never kept around.
+async fn fetch(
+    source: &Plugin,
+    resolved: &ResolvedPlugin,
+    cache_key: Option<&str>,
+    options: &InstallOptions,
+    line: &progress::Line,
+    check: impl Fn(&Download) -> Result<()>,
+) -> Result<Download> {
+    let url = &resolved.url;
+    let cache = cache::Cache::new(&options.cache_dir);
+
+    if let Some(key) = cache_key {
+        if let Some(body) = cache.get(key).await {
+            let download = Download {
+                body,
+                content_type: None,
+            };
+
+            match check(&download) {
+                Ok(()) => return Ok(download),
+                Err(e) if options.offline => return Err(e),
+                Err(_) => {
+                    let _ = cache.remove(key).await;
+                }
+            }
+        }
+    }
+
+    if options.offline {
+        return Err(OfflineError::NotCached.into());
+    }
+
+    line.set(Stage::Downloading {
+        bytes: 0,
+        total: None,
+    });
+
+    let on_progress = |bytes, total| line.set(Stage::Downloading { bytes, total });
+    let download = source
+        .download(resolved, &options.download, &on_progress)
+        .await?;
+
+    check(&download)?;
+
+    // Failing to cache an archive is no reason not to install it.
+    if let Some(key) = cache_key {
+        if let Err(e) = cache.put(key, &download.body).await {
+            line.warn(&format!("Warning: failed to cache {} -- {}", url, e));
+        }
+    }
+
+    Ok(download)
+}
+
+pub fn sha256_hex(bytes: &[u8]) -> String {
+    use sha2::{Digest, Sha256};
+
+    format!("{:x}", Sha256::digest(bytes))
+}
+
+pub fn sha512_hex(bytes: &[u8]) -> String {
+    use sha2::{Digest, Sha512};
+
+    format!("{:x}", Sha512::digest(bytes))
+}
+
+// Unpacks a plugin’s archive into `dest`. Archives from Git hosts wrap everything in a single
+// directory named however the host likes (e.g. ‘vim-surround-master’), so if there is one its
+// contents are used instead.
+async fn unpack_plugin(bytes: &[u8], format: Format, dest: &Path) -> Result<()> {
+    use std::fs;
+
+    // Unpacking into a hidden directory first means that the checks against paths escaping the
+    // directory still apply.
+    let name = dest.file_name().unwrap_or_default().to_string_lossy();
+    let tmp = dest.with_file_name(format!(".{}.tmp", name));
+
+    if tmp.exists() {
+        remove_path(&tmp).await?;
+    }
+
+    archive::unpack(bytes, format, &tmp)?;
+
+    let mut entries = fs::read_dir(&tmp)?.collect::<Result<Vec<_>, _>>()?;
+    let root = match entries.pop() {
+        Some(entry) if entries.is_empty() && entry.file_type()?.is_dir() => entry.path(),
+        _ => tmp.clone(),
+    };
+
+    if dest.exists() {
+        remove_path(dest).await?;
+    }
+
+    fs::rename(&root, dest)?;
+
+    if tmp.exists() {
+        remove_path(&tmp).await?;
+    }
+
+    Ok(())
+}
+
+// Makes sure that each plugin gets a directory of its own, including plugins that are already
+// installed and are not being replaced.
+fn check_dir_names(plugins: &[PluginSpec], state: &State) -> Result<()> {
+    use std::{collections::HashMap, path::Component};
+
+    let ids: Vec<_> = plugins.iter().map(|p| p.source.id()).collect();
+    let mut taken: HashMap<PathBuf, &str> = state
+        .plugins
+        .iter()
+        .filter(|(id, _)| !ids.contains(id))
+        .map(|(id, plugin)| (plugin.dir.clone(), id.as_str()))
+        .collect();
+
+    for (plugin, id) in plugins.iter().zip(&ids) {
+        let name = plugin.dir_name();
+
+        // Names starting with a dot are reserved for strand’s own files.
+        let mut components = Path::new(&name).components();
+        let is_single_component = matches!(
+            (components.next(), components.next()),
+            (Some(Component::Normal(_)), None)
+        );
 
-    let tar = GzDecoder::new(bytes);
-    let mut archive = Archive::new(tar);
-    archive.unpack(path)?;
+        if !is_single_component || name.starts_with('.') {
+            bail!(
+                "‘{}’ is not a valid directory name for {} -- choose another with ‘as:’",
+                name,
+                id
+            );
+        }
+
+        if let Some(other) = taken.insert(plugin.install_dir(), id) {
+            bail!(
+                "{} and {} would both be installed to ‘{}’ -- use ‘as:’ to give one of them a different name",
+                other,
+                id,
+                name
+            );
+        }
+    }
 
     Ok(())
 }
 
-pub async fn install_plugins(plugins: Vec<Plugin>, dir: PathBuf) -> Result<()> {
+// Passing a lockfile installs the exact versions it records. Plugins that are already installed at
+// the right version according to `state` are left alone. Offline, plugins the lockfile does not
+// mention stay at the version they are installed at, since there is no way to check for a newer
+// one. Returns what happened to each of the given plugins, along with their state.
+pub async fn install_plugins(
+    plugins: Vec<PluginSpec>,
+    pack_dir: PathBuf,
+    lockfile: Option<&Lockfile>,
+    state: &State,
+    options: &InstallOptions,
+) -> Result<Report> {
+    check_dir_names(&plugins, state)?;
+
+    let limiter = limit::Limiter::new(options.jobs, options.jobs_per_host);
+    let progress = progress::Progress::new(options.progress);
+    let cancelled = Arc::new(AtomicBool::new(false));
     let mut tasks = Vec::with_capacity(plugins.len());
 
     plugins.into_iter().for_each(|p| {
-        let dir = dir.clone();
-        tasks.push(task::spawn(async move { p.install_plugin(dir).await }));
+        let pack_dir = pack_dir.clone();
+        let options = options.clone();
+        let limiter = limiter.clone();
+        let progress = progress.clone();
+        let cancelled = cancelled.clone();
+        let id = p.source.id();
+        let installed = state.plugins.get(&id).cloned();
+        let pin = lockfile
+            .and_then(|l| l.plugins.get(&id).cloned())
+            .or_else(|| match &installed {
+                Some(installed) if options.offline => Some(installed.version.clone()),
+                _ => None,
+            });
+        tasks.push(task::spawn(async move {
+            // Plugins are timed from when it is their turn, not from when they started waiting.
+            let permit = limiter.acquire(&p.source.host()).await;
+            let started = Instant::now();
+            let result = if cancelled.load(Ordering::SeqCst) {
+                Err(anyhow!("not installed since another plugin failed"))
+            } else {
+                p.install_plugin(pack_dir, pin, installed.clone(), options, permit, progress)
+                    .await
+            };
+            (id, installed, result, started.elapsed())
+        }));
     });
 
-    for task in tasks {
-        task.await?;
+    let mut report = Report {
+        state: State::default(),
+        plugins: Vec::with_capacity(tasks.len()),
+        discarded: false,
+    };
+
+    // Unless told to fail fast, every plugin gets the chance to finish so that one failing does
+    // not hide the others.
+    let mut tasks = tasks.into_iter();
+
+    while let Some(task) = tasks.next() {
+        let (id, previous, result, duration) = task.await;
+
+        let outcome = match result {
+            Ok(plugin) => {
+                let outcome = match previous {
+                    Some(previous) if previous == plugin => Outcome::UpToDate,
+                    _ => Outcome::Installed,
+                };
+                report.state.plugins.insert(id.clone(), plugin);
+                outcome
+            }
+            Err(e) if options.fail_fast => {
+                // async-std cannot cancel tasks, and ones left running would carry on writing to
+                // the pack directory after we return. Plugins that have not started yet are
+                // skipped, and the rest are waited for.
+                cancelled.store(true, Ordering::SeqCst);
+
+                for task in tasks {
+                    let _ = task.await;
+                }
+
+                progress.close();
+                return Err(e.context(format!("failed to install {}", id)));
+            }
+            Err(e) => {
+                if let Some(previous) = previous {
+                    report.state.plugins.insert(id.clone(), previous);
+                }
+                Outcome::Failed(e)
+            }
+        };
+
+        report.plugins.push(PluginReport {
+            id,
+            outcome,
+            duration,
+        });
+    }
+
+    progress.close();
+
+    Ok(report)
+}
+
+async fn remove_path(path: &Path) -> Result<()> {
+    use async_std::fs;
+
+    if fs::metadata(path).await?.is_dir() {
+        fs::remove_dir_all(path).await?;
+    } else {
+        fs::remove_file(path).await?;
     }
 
     Ok(())
@@ -322,4 +1438,665 @@ mod tests {
             home_dir.join("bar/baz/quux/foo.txt")
         );
     }
+
+    fn tar_gz(files: &[(&str, &str)]) -> Vec<u8> {
+        use flate2::{write::GzEncoder, Compression};
+
+        let mut builder = tar::Builder::new(GzEncoder::new(Vec::new(), Compression::default()));
+
+        for (path, contents) in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(contents.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder
+                .append_data(&mut header, path, contents.as_bytes())
+                .unwrap();
+        }
+
+        builder.into_inner().unwrap().finish().unwrap()
+    }
+
+    #[async_std::test]
+    async fn test_unpack_plugin() {
+        let dir = std::env::temp_dir().join("strand-test-unpack-plugin");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+
+        // The top-level directory is stripped when it is the only thing in the archive…
+        let archive = tar_gz(&[
+            ("vim-surround-master/plugin/surround.vim", "\" surround"),
+            ("vim-surround-master/doc/surround.txt", "*surround.txt*"),
+        ]);
+        unpack_plugin(&archive, Format::TarGz, &dir.join("vim-surround"))
+            .await
+            .unwrap();
+        assert!(dir.join("vim-surround/plugin/surround.vim").is_file());
+
+        // …but not otherwise.
+        let archive = tar_gz(&[("plugin/qlist.vim", ""), ("doc/qlist.txt", "")]);
+        unpack_plugin(&archive, Format::TarGz, &dir.join("vim-qlist"))
+            .await
+            .unwrap();
+        assert!(dir.join("vim-qlist/plugin/qlist.vim").is_file());
+
+        let entries: Vec<_> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(entries.len(), 2);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[async_std::test]
+    async fn test_install_offline() {
+        let dir = std::env::temp_dir().join("strand-test-install-offline");
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let options = InstallOptions {
+            run_timeout: Duration::from_secs(10),
+            cache_dir: dir.join("cache"),
+            offline: true,
+            fail_fast: false,
+            jobs: 8,
+            jobs_per_host: 4,
+            progress: ProgressStyle::Silent,
+            download: DownloadOptions::default(),
+        };
+
+        let surround: Plugin = "tpope/vim-surround:4a97465".parse().unwrap();
+        let url = surround
+            .resolve(None, true, &options.download)
+            .await
+            .unwrap()
+            .url;
+        let archive = tar_gz(&[("vim-surround-4a97465/plugin/surround.vim", "")]);
+        cache::Cache::new(&options.cache_dir)
+            .put(&url, &archive)
+            .await
+            .unwrap();
+
+        let report = install_plugins(
+            vec![surround.into()],
+            dir.join("pack"),
+            None,
+            &State::default(),
+            &options,
+        )
+        .await
+        .unwrap();
+        assert!(dir
+            .join("pack/start/vim-surround/plugin/surround.vim")
+            .is_file());
+        assert_eq!(
+            report.state.plugins["github@tpope/vim-surround:4a97465"]
+                .version
+                .commit
+                .as_deref(),
+            Some("4a97465")
+        );
+
+        // Failures are reported for each plugin without affecting the others.
+        let plugins = vec![
+            "tpope/vim-surround:4a97465"
+                .parse::<Plugin>()
+                .unwrap()
+                .into(),
+            "tpope/vim-commentary".parse::<Plugin>().unwrap().into(),
+            "tpope/vim-repeat:1234567".parse::<Plugin>().unwrap().into(),
+        ];
+        let report = install_plugins(plugins, dir.join("pack"), None, &report.state, &options)
+            .await
+            .unwrap();
+        let outcomes: Vec<_> = report
+            .plugins
+            .iter()
+            .map(|PluginReport { id, outcome, .. }| match outcome {
+                Outcome::Installed => format!("{}: installed", id),
+                Outcome::UpToDate => format!("{}: up to date", id),
+                Outcome::Failed(e) => format!("{}: {}", id, e),
+                Outcome::RolledBack => format!("{}: rolled back", id),
+            })
+            .collect();
+        assert_eq!(
+            outcomes,
+            [
+                "github@tpope/vim-surround:4a97465: up to date",
+                "github@tpope/vim-commentary: no commit recorded in the lockfile or state file to install",
+                "github@tpope/vim-repeat:1234567: not in the download cache",
+            ]
+        );
+        assert_eq!(report.failed(), 2);
+
+        let results = serde_json::to_value(report.results()).unwrap();
+        assert_eq!(results[0]["status"], "up_to_date");
+        assert_eq!(results[0]["ref"], "4a97465");
+        assert_eq!(results[2]["status"], "failed");
+        assert_eq!(results[2]["source"], serde_json::Value::Null);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[async_std::test]
+    async fn test_install_cached_archive() {
+        use std::sync::{Arc, Mutex};
+
+        let dir = std::env::temp_dir().join("strand-test-install-cached-archive");
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let served = Arc::new(Mutex::new(tar_gz(&[
+            ("plugin/qlist.vim", "1"),
+            ("README", ""),
+        ])));
+        let server = {
+            let served = served.clone();
+            test_server::serve(move |path| match path {
+                "/vim-qlist.tar.gz" => test_server::Response::ok(served.lock().unwrap().clone()),
+                _ => test_server::Response::not_found(),
+            })
+        };
+
+        let mut options = InstallOptions {
+            run_timeout: Duration::from_secs(10),
+            cache_dir: dir.join("cache"),
+            offline: false,
+            fail_fast: true,
+            jobs: 8,
+            jobs_per_host: 4,
+            progress: ProgressStyle::Silent,
+            download: DownloadOptions::default(),
+        };
+
+        let qlist = |sha256: &str| PluginSpec {
+            sha256: Some(sha256.into()),
+            ..format!("{}/vim-qlist.tar.gz", server)
+                .parse::<Plugin>()
+                .unwrap()
+                .into()
+        };
+        let file = dir.join("pack/start/vim-qlist/plugin/qlist.vim");
+        let first = sha256_hex(&served.lock().unwrap());
+
+        let report = install_plugins(
+            vec![qlist(&first)],
+            dir.join("pack"),
+            None,
+            &State::default(),
+            &options,
+        )
+        .await
+        .unwrap();
+        assert_eq!(std::fs::read_to_string(&file).unwrap(), "1");
+
+        // The archive at the URL changes, and the config file is changed to expect the new one,
+        // which has to be downloaded rather than taken from the cache.
+        *served.lock().unwrap() = tar_gz(&[("plugin/qlist.vim", "2"), ("README", "")]);
+        let second = sha256_hex(&served.lock().unwrap());
+
+        let report = install_plugins(
+            vec![qlist(&second)],
+            dir.join("pack"),
+            None,
+            &report.state,
+            &options,
+        )
+        .await
+        .unwrap();
+        assert_eq!(std::fs::read_to_string(&file).unwrap(), "2");
+
+        // An archive that does not match is not cached…
+        let wrong = "0".repeat(64);
+        let result = install_plugins(
+            vec![qlist(&wrong)],
+            dir.join("pack"),
+            None,
+            &report.state,
+            &options,
+        )
+        .await;
+        assert!(result.is_err());
+
+        let cache = cache::Cache::new(&options.cache_dir);
+        assert_eq!(cache.get(&format!("sha256:{}", wrong)).await, None);
+
+        // …and one that no longer matches what it is cached under is downloaded again.
+        cache
+            .put(&format!("sha256:{}", second), b"<html></html>")
+            .await
+            .unwrap();
+        install_plugins(
+            vec![qlist(&second)],
+            dir.join("pack"),
+            None,
+            &State::default(),
+            &options,
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            cache.get(&format!("sha256:{}", second)).await.as_deref(),
+            Some(&served.lock().unwrap()[..])
+        );
+
+        // Both versions can be installed from the cache.
+        options.offline = true;
+
+        for (sha256, contents) in &[(&first, "1"), (&second, "2")] {
+            let pack_dir = dir.join("offline").join(contents);
+            install_plugins(
+                vec![qlist(sha256)],
+                pack_dir.clone(),
+                None,
+                &State::default(),
+                &options,
+            )
+            .await
+            .unwrap();
+            let file = pack_dir.join("start/vim-qlist/plugin/qlist.vim");
+            assert_eq!(std::fs::read_to_string(file).unwrap(), *contents);
+        }
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[async_std::test]
+    async fn test_install_archive_hash_changed() {
+        use std::sync::{Arc, Mutex};
+
+        let dir = std::env::temp_dir().join("strand-test-install-archive-hash-changed");
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let served = Arc::new(Mutex::new(tar_gz(&[
+            ("plugin/qlist.vim", "1"),
+            ("README", ""),
+        ])));
+        let server = {
+            let served = served.clone();
+            test_server::serve(move |path| match path {
+                "/vim-qlist.tar.gz" => test_server::Response::ok(served.lock().unwrap().clone()),
+                _ => test_server::Response::not_found(),
+            })
+        };
+
+        let options = InstallOptions {
+            run_timeout: Duration::from_secs(10),
+            cache_dir: dir.join("cache"),
+            offline: false,
+            fail_fast: true,
+            jobs: 8,
+            jobs_per_host: 4,
+            progress: ProgressStyle::Silent,
+            download: DownloadOptions::default(),
+        };
+
+        let qlist = |sha512: &str| PluginSpec {
+            sha512: Some(sha512.into()),
+            ..format!("{}/vim-qlist.tar.gz", server)
+                .parse::<Plugin>()
+                .unwrap()
+                .into()
+        };
+        let file = dir.join("pack/start/vim-qlist/plugin/qlist.vim");
+
+        let sha512 = sha512_hex(&served.lock().unwrap());
+        let report = install_plugins(
+            vec![qlist(&sha512)],
+            dir.join("pack"),
+            None,
+            &State::default(),
+            &options,
+        )
+        .await
+        .unwrap();
+        assert_eq!(std::fs::read_to_string(&file).unwrap(), "1");
+
+        // A plugin with only a SHA-512 hash is installed again once its hash changes.
+        *served.lock().unwrap() = tar_gz(&[("plugin/qlist.vim", "2"), ("README", "")]);
+        let sha512 = sha512_hex(&served.lock().unwrap());
+        let report = install_plugins(
+            vec![qlist(&sha512)],
+            dir.join("pack"),
+            None,
+            &report.state,
+            &options,
+        )
+        .await
+        .unwrap();
+        assert_eq!(std::fs::read_to_string(&file).unwrap(), "2");
+        assert!(matches!(report.plugins[0].outcome, Outcome::Installed));
+
+        // …but not otherwise.
+        let report = install_plugins(
+            vec![qlist(&sha512)],
+            dir.join("pack"),
+            None,
+            &report.state,
+            &options,
+        )
+        .await
+        .unwrap();
+        assert!(matches!(report.plugins[0].outcome, Outcome::UpToDate));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[async_std::test]
+    async fn test_install_archive_format_changed() {
+        let dir = std::env::temp_dir().join("strand-test-install-archive-format-changed");
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let archive = tar_gz(&[("plugin/qlist.vim", ""), ("README", "")]);
+        let server = test_server::serve(move |path| match path {
+            "/vim-qlist" => test_server::Response::ok(archive.clone()),
+            _ => test_server::Response::not_found(),
+        });
+
+        let options = InstallOptions {
+            run_timeout: Duration::from_secs(10),
+            cache_dir: dir.join("cache"),
+            offline: false,
+            fail_fast: true,
+            jobs: 8,
+            jobs_per_host: 4,
+            progress: ProgressStyle::Silent,
+            download: DownloadOptions::default(),
+        };
+
+        let qlist = |format| PluginSpec {
+            format,
+            ..format!("{}/vim-qlist", server)
+                .parse::<Plugin>()
+                .unwrap()
+                .into()
+        };
+
+        let mut state = State::default();
+        let mut outcomes = Vec::new();
+
+        for format in &[None, Some(Format::TarGz), Some(Format::TarGz)] {
+            let report = install_plugins(
+                vec![qlist(*format)],
+                dir.join("pack"),
+                None,
+                &state,
+                &options,
+            )
+            .await
+            .unwrap();
+            outcomes.push(matches!(report.plugins[0].outcome, Outcome::Installed));
+            state = report.state;
+        }
+
+        // Giving a format where there was none means unpacking the archive again.
+        assert_eq!(outcomes, [true, true, false]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[async_std::test]
+    async fn test_install_fail_fast() {
+        let dir = std::env::temp_dir().join("strand-test-install-fail-fast");
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let missing = test_server::serve(|_| test_server::Response::not_found());
+        let archive = tar_gz(&[("plugin/slow.vim", ""), ("README", "")]);
+        let slow = test_server::serve(move |_| {
+            std::thread::sleep(Duration::from_millis(300));
+            test_server::Response::ok(archive.clone())
+        });
+
+        let options = InstallOptions {
+            run_timeout: Duration::from_secs(10),
+            cache_dir: dir.join("cache"),
+            offline: false,
+            fail_fast: true,
+            jobs: 8,
+            jobs_per_host: 4,
+            progress: ProgressStyle::Silent,
+            download: DownloadOptions::default(),
+        };
+
+        let plugins = vec![
+            format!("{}/vim-missing.tar.gz", missing)
+                .parse::<Plugin>()
+                .unwrap()
+                .into(),
+            format!("{}/vim-slow.tar.gz", slow)
+                .parse::<Plugin>()
+                .unwrap()
+                .into(),
+        ];
+
+        let staging = Staging::new(&dir.join("pack"), true).await.unwrap();
+        let path = staging.path().to_path_buf();
+        let result =
+            install_plugins(plugins, path.clone(), None, &State::default(), &options).await;
+        assert!(format!("{:#}", result.err().unwrap()).contains("vim-missing"));
+
+        // Nothing is still writing to the staging directory by the time it is thrown away.
+        staging.discard().await.unwrap();
+        task::sleep(Duration::from_millis(500)).await;
+        assert!(!path.exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_git_repo_urls() {
+        let urls = |s: &str| {
+            let repo: GitRepo = s.parse().unwrap();
+            (repo.id(), repo.repo_url(), repo.archive_url("v1"))
+        };
+
+        assert_eq!(
+            urls("tpope/vim-surround"),
+            (
+                "github@tpope/vim-surround".into(),
+                "https://github.com/tpope/vim-surround.git".into(),
+                "https://codeload.github.com/tpope/vim-surround/tar.gz/v1".into()
+            )
+        );
+        assert_eq!(
+            urls("gitlab(git.corp.example)@team/vim-foo:v1"),
+            (
+                "gitlab(git.corp.example)@team/vim-foo:v1".into(),
+                "https://git.corp.example/team/vim-foo.git".into(),
+                "https://git.corp.example/team/vim-foo/-/archive/v1/team-v1.tar.gz".into()
+            )
+        );
+        assert_eq!(
+            urls("github(ghe.corp.example)@team/vim-foo").2,
+            "https://ghe.corp.example/team/vim-foo/archive/v1.tar.gz"
+        );
+        assert_eq!(
+            urls("bitbucket(http://bitbucket.local/)@TEAM/vim-foo").2,
+            "http://bitbucket.local/rest/api/latest/projects/TEAM/repos/vim-foo/archive?at=v1&format=tar.gz"
+        );
+        assert_eq!(
+            urls("codeberg@user/vim-foo").2,
+            "https://codeberg.org/user/vim-foo/archive/v1.tar.gz"
+        );
+        assert_eq!(
+            urls("sourcehut@user/vim-foo").1,
+            "https://git.sr.ht/~user/vim-foo"
+        );
+        assert_eq!(
+            urls("srht@~user/vim-foo").2,
+            "https://git.sr.ht/~user/vim-foo/archive/v1.tar.gz"
+        );
+
+        assert!(matches!(
+            "forgejo@user/vim-foo".parse::<GitRepo>(),
+            Err(GitRepoParseError::MissingHost(_))
+        ));
+        assert!(matches!(
+            "gitea()@user/vim-foo".parse::<GitRepo>(),
+            Err(GitRepoParseError::MalformedHost(_))
+        ));
+    }
+
+    #[test]
+    fn test_parse_plugin() {
+        let parse = |s: &str| {
+            let plugin: Plugin = s.parse().unwrap();
+            (plugin.id(), plugin.default_name())
+        };
+
+        assert!(matches!("tpope/vim-surround".parse(), Ok(Plugin::Git(_))));
+        assert!(matches!(
+            "https://example.com/vim-qlist.tar.gz".parse(),
+            Ok(Plugin::Archive(_))
+        ));
+        assert_eq!(
+            parse("ssh://git@git.corp.example/team/vim-foo.git#v1"),
+            (
+                "ssh://git@git.corp.example/team/vim-foo.git#v1".into(),
+                "vim-foo".into()
+            )
+        );
+        assert_eq!(
+            parse("https://git.corp.example/vim-foo.git"),
+            (
+                "https://git.corp.example/vim-foo.git".into(),
+                "vim-foo".into()
+            )
+        );
+        assert!(matches!(
+            "file:///srv/git/vim-foo".parse(),
+            Ok(Plugin::GitClone(_))
+        ));
+        assert_eq!(
+            "git@git.corp.example:vim-foo.git"
+                .parse::<GitCloneRepo>()
+                .unwrap()
+                .name(),
+            "vim-foo"
+        );
+
+        assert_eq!(
+            url_host("git@git.corp.example:vim-foo.git"),
+            "git.corp.example"
+        );
+        assert_eq!(
+            "codeberg@user/vim-foo".parse::<Plugin>().unwrap().host(),
+            "codeberg.org"
+        );
+    }
+
+    #[test]
+    fn test_check_dir_names() {
+        let plugins: Vec<PluginSpec> = yaml::from_str(
+            "
+- Git: tpope/vim-surround
+- Archive: https://example.com/vim-qlist.tar.gz
+- Git: bitbucket@vim-plugins-mirror/vim-surround
+  as: vim-surround-mirror
+- Git: gitlab@YaBoiBurner/vim-surround
+  opt: true
+",
+        )
+        .unwrap();
+
+        let names: Vec<_> = plugins.iter().map(PluginSpec::dir_name).collect();
+        assert_eq!(
+            names,
+            vec![
+                "vim-surround",
+                "vim-qlist",
+                "vim-surround-mirror",
+                "vim-surround"
+            ]
+        );
+        assert!(check_dir_names(&plugins, &State::default()).is_ok());
+
+        let plugins: Vec<PluginSpec> = yaml::from_str(
+            "
+- Git: tpope/vim-surround
+- Git: bitbucket@vim-plugins-mirror/vim-surround
+",
+        )
+        .unwrap();
+
+        let error = check_dir_names(&plugins, &State::default()).unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("would both be installed to ‘vim-surround’"));
+    }
+
+    #[test]
+    fn test_parse_config() {
+        let config: Config = yaml::from_str(
+            "
+pack_dir: ~/.vim/pack/strand
+plugins:
+  - Git: tpope/vim-surround
+  - Git: junegunn/fzf:0.20.0
+    run: ./install --bin
+  - Archive: https://codeload.github.com/romainl/vim-qlist/tar.gz/master
+",
+        )
+        .unwrap();
+
+        let ids: Vec<_> = config.plugins.iter().map(|p| p.source.id()).collect();
+        assert_eq!(
+            ids,
+            vec![
+                "github@tpope/vim-surround",
+                "github@junegunn/fzf:0.20.0",
+                "https://codeload.github.com/romainl/vim-qlist/tar.gz/master",
+            ]
+        );
+        assert_eq!(config.plugins[0].run, None);
+        assert_eq!(config.plugins[1].run.as_deref(), Some("./install --bin"));
+        assert_eq!(config.run_timeout, 300);
+
+        // The other formats share the same layout.
+        let toml = parse_config(
+            r#"
+pack_dir = "~/.vim/pack/strand"
+run_timeout = 600
+
+[[plugins]]
+Git = "junegunn/fzf:0.20.0"
+run = "./install --bin"
+"#,
+            ConfigFormat::Toml,
+        )
+        .unwrap();
+        let json = parse_config(
+            r#"{
+                "pack_dir": "~/.vim/pack/strand",
+                "run_timeout": 600,
+                "plugins": [{ "Git": "junegunn/fzf:0.20.0", "run": "./install --bin" }]
+            }"#,
+            ConfigFormat::Json,
+        )
+        .unwrap();
+
+        for config in &[toml, json] {
+            assert_eq!(config.plugins[0].source.id(), "github@junegunn/fzf:0.20.0");
+            assert_eq!(config.plugins[0].run.as_deref(), Some("./install --bin"));
+            assert_eq!(config.run_timeout, 600);
+        }
+
+        assert_eq!(
+            ConfigFormat::from_path(Path::new("config.yml")).unwrap(),
+            ConfigFormat::Yaml
+        );
+        assert!(ConfigFormat::from_path(Path::new("config.ini")).is_err());
+    }
+
+    #[test]
+    fn test_check_integrity() {
+        let mut plugin = PluginSpec::from(Plugin::from_str("tpope/vim-surround").unwrap());
+        plugin.sha256 = Some(sha256_hex(b"archive").to_uppercase());
+        assert!(plugin.check_integrity(b"archive", "url").is_ok());
+
+        plugin.sha512 = Some(sha512_hex(b"something else"));
+        let error = plugin.check_integrity(b"archive", "url").unwrap_err();
+        assert!(error
+            .to_string()
+            .starts_with("SHA-512 of archive downloaded from url"));
+    }
 }
@@ -0,0 +1,113 @@
+//! Limits on how many plugins are downloaded at once, both overall and from any one host, so that
+//! large configs neither trip servers’ rate limits nor let one slow host hold up the rest.
+
+use std::{
+    collections::HashMap,
+    future,
+    sync::{Arc, Mutex},
+    task::{Poll, Waker},
+};
+
+#[derive(Default)]
+struct Slots {
+    total: usize,
+    hosts: HashMap<String, usize>,
+    waiting: Vec<Waker>,
+}
+
+/// Hands out permits to download, never more than `jobs` at once or `per_host` for the same host.
+#[derive(Clone)]
+pub struct Limiter {
+    jobs: usize,
+    per_host: usize,
+    slots: Arc<Mutex<Slots>>,
+}
+
+/// Allows one download from a host until it is dropped.
+pub struct Permit {
+    host: String,
+    slots: Arc<Mutex<Slots>>,
+}
+
+impl Limiter {
+    /// Both limits are at least one, and there is no point in the per-host one being higher than
+    /// the overall one.
+    pub fn new(jobs: usize, per_host: usize) -> Self {
+        let jobs = jobs.max(1);
+
+        Self {
+            jobs,
+            per_host: per_host.clamp(1, jobs),
+            slots: Default::default(),
+        }
+    }
+
+    /// Waits until a download from the given host is allowed.
+    pub async fn acquire(&self, host: &str) -> Permit {
+        future::poll_fn(|cx| {
+            let mut slots = self.slots.lock().unwrap();
+            let from_host = slots.hosts.get(host).copied().unwrap_or_default();
+
+            if slots.total < self.jobs && from_host < self.per_host {
+                slots.total += 1;
+                slots.hosts.insert(host.into(), from_host + 1);
+
+                Poll::Ready(Permit {
+                    host: host.into(),
+                    slots: self.slots.clone(),
+                })
+            } else {
+                slots.waiting.push(cx.waker().clone());
+                Poll::Pending
+            }
+        })
+        .await
+    }
+}
+
+impl Drop for Permit {
+    // Every waiting download is woken, since there is no telling which of them can now go ahead.
+    // Those that cannot simply go back to waiting.
+    fn drop(&mut self) {
+        let mut slots = self.slots.lock().unwrap();
+        slots.total -= 1;
+
+        if let Some(from_host) = slots.hosts.get_mut(&self.host) {
+            *from_host -= 1;
+        }
+
+        for waker in slots.waiting.drain(..) {
+            waker.wake();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_std::future::timeout;
+    use std::time::Duration;
+
+    #[async_std::test]
+    async fn test_limiter() {
+        let limiter = Limiter::new(3, 2);
+        let wait = Duration::from_millis(50);
+
+        let a1 = limiter.acquire("a").await;
+        let _a2 = limiter.acquire("a").await;
+
+        // Host ‘a’ is at its limit, but other hosts are not…
+        assert!(timeout(wait, limiter.acquire("a")).await.is_err());
+        let _b1 = limiter.acquire("b").await;
+
+        // …until the overall limit is reached too.
+        assert!(timeout(wait, limiter.acquire("b")).await.is_err());
+
+        let waiting = async_std::task::spawn({
+            let limiter = limiter.clone();
+            async move { limiter.acquire("a").await }
+        });
+        drop(a1);
+        assert!(timeout(Duration::from_secs(5), waiting).await.is_ok());
+    }
+}
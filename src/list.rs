@@ -0,0 +1,321 @@
+//! Describing what is in the pack directory, for `strand list`.
+
+use crate::{progress::size, PluginSpec, State};
+use anyhow::Result;
+use serde::Serialize;
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+#[derive(Serialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum ListStatus {
+    /// In the config file and installed.
+    Installed,
+    /// Installed with `strand install`, so it will be removed by the next sync.
+    Temporary,
+    /// Recorded as installed, but its directory has since been deleted.
+    Missing,
+    /// In the config file but not installed yet.
+    NotInstalled,
+    /// A directory in the pack directory that strand did not put there.
+    Unmanaged,
+}
+
+/// A row of `strand list`. Only the status and directory are known for every row.
+#[derive(Serialize)]
+pub struct ListEntry {
+    pub id: Option<String>,
+    pub status: ListStatus,
+    /// Relative to the pack directory.
+    pub dir: PathBuf,
+    pub source: Option<String>,
+    #[serde(rename = "ref")]
+    pub git_ref: Option<String>,
+    pub commit: Option<String>,
+    pub sha256: Option<String>,
+    /// When the plugin was installed, in seconds since the Unix epoch.
+    pub installed_at: Option<u64>,
+    /// The total size of the files in the directory, in bytes.
+    pub size: Option<u64>,
+}
+
+impl ListEntry {
+    fn new(id: Option<String>, status: ListStatus, dir: PathBuf) -> Self {
+        Self {
+            id,
+            status,
+            dir,
+            source: None,
+            git_ref: None,
+            commit: None,
+            sha256: None,
+            installed_at: None,
+            size: None,
+        }
+    }
+}
+
+// Symlinks are counted as themselves rather than whatever they point to.
+fn dir_size(path: &Path) -> io::Result<u64> {
+    let mut total = 0;
+
+    for entry in fs::read_dir(path)? {
+        let entry = entry?;
+        let metadata = entry.path().symlink_metadata()?;
+
+        total += if metadata.is_dir() {
+            dir_size(&entry.path())?
+        } else {
+            metadata.len()
+        };
+    }
+
+    Ok(total)
+}
+
+/// Lists the plugins in the config file in order, then any others strand has installed, then any
+/// directories it knows nothing about.
+pub fn list(plugins: &[PluginSpec], state: &State, pack_dir: &Path) -> Result<Vec<ListEntry>> {
+    let ids: Vec<_> = plugins.iter().map(|p| p.source.id()).collect();
+
+    let configured = plugins
+        .iter()
+        .zip(&ids)
+        .map(|(plugin, id)| (id, plugin.install_dir()));
+    let temporary = state
+        .plugins
+        .iter()
+        .filter(|(id, _)| !ids.contains(id))
+        .map(|(id, plugin)| (id, plugin.dir.clone()));
+
+    let mut entries = Vec::new();
+
+    for (id, dir) in configured.chain(temporary) {
+        let installed = match state.plugins.get(id) {
+            Some(installed) => installed,
+            None => {
+                entries.push(ListEntry::new(
+                    Some(id.clone()),
+                    ListStatus::NotInstalled,
+                    dir,
+                ));
+                continue;
+            }
+        };
+
+        let status = if !installed.is_present(pack_dir) {
+            ListStatus::Missing
+        } else if ids.contains(id) {
+            ListStatus::Installed
+        } else {
+            ListStatus::Temporary
+        };
+
+        entries.push(ListEntry {
+            source: Some(installed.source.clone()),
+            git_ref: installed.git_ref.clone(),
+            commit: installed.version.commit.clone(),
+            sha256: installed.version.sha256.clone(),
+            installed_at: installed.installed_at,
+            size: dir_size(&pack_dir.join(&installed.dir)).ok(),
+            ..ListEntry::new(Some(id.clone()), status, installed.dir.clone())
+        });
+    }
+
+    // Hidden directories are strand’s own, e.g. plugins part of the way through being unpacked.
+    for kind in &["start", "opt"] {
+        let kind_dir = pack_dir.join(kind);
+
+        if !kind_dir.is_dir() {
+            continue;
+        }
+
+        let mut unmanaged = Vec::new();
+
+        for entry in fs::read_dir(&kind_dir)? {
+            let entry = entry?;
+            let dir = Path::new(kind).join(entry.file_name());
+            let hidden = entry.file_name().to_string_lossy().starts_with('.');
+            let known = state.plugins.values().any(|plugin| plugin.dir == dir);
+
+            if !hidden && !known {
+                unmanaged.push(ListEntry {
+                    size: dir_size(&entry.path()).ok(),
+                    ..ListEntry::new(None, ListStatus::Unmanaged, dir)
+                });
+            }
+        }
+
+        unmanaged.sort_by(|a, b| a.dir.cmp(&b.dir));
+        entries.extend(unmanaged);
+    }
+
+    Ok(entries)
+}
+
+// Formats seconds since the Unix epoch as a UTC date and time, e.g. ‘2019-12-01 14:03’.
+pub(crate) fn format_time(seconds: u64) -> String {
+    let (days, seconds) = (seconds / 86_400, seconds % 86_400);
+
+    // The inverse of the calculation in `download::parse_http_date`.
+    let days = days + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month + 2) / 5 + 1;
+    let (year, month) = match month {
+        10 | 11 => (era * 400 + year_of_era + 1, month - 9),
+        _ => (era * 400 + year_of_era, month + 3),
+    };
+
+    format!(
+        "{}-{:02}-{:02} {:02}:{:02}",
+        year,
+        month,
+        day,
+        seconds / 3_600,
+        seconds % 3_600 / 60
+    )
+}
+
+/// Prints the entries as a table, one plugin per line. Times are in UTC.
+pub fn print_list(entries: &[ListEntry]) {
+    let header = ["DIRECTORY", "PLUGIN", "VERSION", "INSTALLED", "SIZE", ""].map(String::from);
+    let rows: Vec<_> = std::iter::once(header)
+        .chain(entries.iter().map(|entry| {
+            let version = match (&entry.git_ref, &entry.commit, &entry.sha256) {
+                (Some(git_ref), Some(commit), _) if git_ref != commit => {
+                    format!("{} @ {:.7}", git_ref, commit)
+                }
+                (_, Some(commit), _) => format!("{:.7}", commit),
+                (_, _, Some(sha256)) => format!("sha256:{:.12}", sha256),
+                _ => String::new(),
+            };
+
+            let note = match entry.status {
+                ListStatus::Installed => "",
+                ListStatus::Temporary => "removed on next sync",
+                ListStatus::Missing => "directory missing",
+                ListStatus::NotInstalled => "not installed",
+                ListStatus::Unmanaged => "not installed by strand",
+            };
+
+            [
+                entry.dir.display().to_string(),
+                entry.id.clone().unwrap_or_default(),
+                version,
+                entry.installed_at.map(format_time).unwrap_or_default(),
+                entry.size.map(size).unwrap_or_default(),
+                note.into(),
+            ]
+        }))
+        .collect();
+
+    print_table(&rows);
+}
+
+/// Prints rows of cells with each column padded to line up.
+pub(crate) fn print_table<R: AsRef<[String]>>(rows: &[R]) {
+    let columns = rows.iter().map(|row| row.as_ref().len()).max();
+    let mut widths = vec![0; columns.unwrap_or_default()];
+
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row.as_ref()) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    for row in rows {
+        let line: Vec<_> = row
+            .as_ref()
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{:width$}", cell, width = width))
+            .collect();
+
+        println!("{}", line.join("  ").trim_end());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{InstalledPlugin, LockedPlugin, Plugin};
+
+    #[test]
+    fn test_format_time() {
+        assert_eq!(format_time(0), "1970-01-01 00:00");
+        assert_eq!(format_time(1_445_412_480), "2015-10-21 07:28");
+        assert_eq!(format_time(951_782_400), "2000-02-29 00:00");
+    }
+
+    #[test]
+    fn test_list() {
+        let dir = std::env::temp_dir().join("strand-test-list");
+        let _ = fs::remove_dir_all(&dir);
+
+        for plugin in &["start/vim-surround", "start/vim-unimpaired", "opt/random"] {
+            fs::create_dir_all(dir.join(plugin)).unwrap();
+        }
+        fs::write(dir.join("start/vim-surround/surround.vim"), "surround").unwrap();
+
+        let installed = |id: &str, dir: &str| {
+            let plugin = InstalledPlugin {
+                source: format!("https://example.com/{}.tar.gz", id),
+                git_ref: None,
+                version: LockedPlugin {
+                    commit: None,
+                    sha256: None,
+                    sha512: None,
+                },
+                dir: dir.into(),
+                run: None,
+                format: None,
+                installed_at: Some(0),
+            };
+
+            (id.to_string(), plugin)
+        };
+
+        let mut state = State::default();
+        state.plugins.extend(vec![
+            installed("github@tpope/vim-surround", "start/vim-surround"),
+            installed("github@tpope/vim-unimpaired", "start/vim-unimpaired"),
+            installed("github@tpope/vim-repeat", "start/vim-repeat"),
+        ]);
+
+        let plugins: Vec<PluginSpec> = [
+            "tpope/vim-surround",
+            "tpope/vim-repeat",
+            "tpope/vim-fugitive",
+        ]
+        .iter()
+        .map(|s| s.parse::<Plugin>().unwrap().into())
+        .collect();
+
+        let entries = list(&plugins, &state, &dir).unwrap();
+        let rows: Vec<_> = entries
+            .iter()
+            .map(|e| (e.dir.to_string_lossy().into_owned(), e.status))
+            .collect();
+
+        assert_eq!(
+            rows,
+            [
+                ("start/vim-surround".into(), ListStatus::Installed),
+                ("start/vim-repeat".into(), ListStatus::Missing),
+                ("start/vim-fugitive".into(), ListStatus::NotInstalled),
+                ("start/vim-unimpaired".into(), ListStatus::Temporary),
+                ("opt/random".into(), ListStatus::Unmanaged),
+            ]
+        );
+        assert_eq!(entries[0].size, Some(8));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
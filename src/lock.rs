@@ -0,0 +1,74 @@
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, path::Path};
+
+/// What a plugin resolved to when it was last installed from the config file. Git plugins record
+/// the commit their reference pointed to, while archive plugins record a hash of the archive’s
+/// contents since that is all we have to go on.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct LockedPlugin {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub commit: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+    /// Only recorded for archives whose SHA-512 hash the config file gives, so that a change to it
+    /// can be noticed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha512: Option<String>,
+}
+
+/// The contents of `strand.lock`, mapping the ID of each plugin in the config file to what it
+/// resolved to.
+#[derive(Serialize, Deserialize, Default)]
+pub struct Lockfile {
+    pub plugins: BTreeMap<String, LockedPlugin>,
+}
+
+impl Lockfile {
+    pub async fn read(path: &Path) -> Result<Self> {
+        use async_std::fs;
+
+        let lockfile = fs::read_to_string(path)
+            .await
+            .with_context(|| format!("failed to read lockfile at {}", path.display()))?;
+
+        Ok(yaml::from_str(&lockfile)?)
+    }
+
+    pub async fn write(&self, path: &Path) -> Result<()> {
+        use async_std::fs;
+
+        fs::write(path, yaml::to_string(self)?).await?;
+
+        Ok(())
+    }
+
+    /// Ensures that the lockfile has an entry for each of the given plugin IDs and nothing else.
+    pub fn check_matches(&self, ids: &[String]) -> Result<()> {
+        let mut unlocked = Vec::new();
+        let mut stale: Vec<_> = self.plugins.keys().map(String::as_str).collect();
+
+        for id in ids {
+            match stale.iter().position(|locked| locked == id) {
+                Some(i) => {
+                    stale.remove(i);
+                }
+                None => unlocked.push(id),
+            }
+        }
+
+        if unlocked.is_empty() && stale.is_empty() {
+            return Ok(());
+        }
+
+        let mut message = String::from("config file and lockfile disagree");
+        unlocked
+            .iter()
+            .for_each(|id| message.push_str(&format!("\n  not in lockfile: {}", id)));
+        stale
+            .iter()
+            .for_each(|id| message.push_str(&format!("\n  not in config file: {}", id)));
+
+        bail!(message)
+    }
+}
@@ -1,76 +1,688 @@
-use anyhow::Result;
+use anyhow::{bail, Result};
 use async_std::fs;
-use std::path::Path;
-use strand::Plugin;
+use serde_json::json;
+use std::{
+    io::{self, IsTerminal},
+    path::Path,
+    process,
+    str::FromStr,
+    time::Duration,
+};
+use strand::{
+    ConfigFormat, DownloadOptions, InstallOptions, Lockfile, Outcome, OutdatedEntry,
+    OutdatedStatus, Plugin, PluginArg, PluginChanges, PluginSpec, ProgressStyle, Report, Staging,
+    State, Update,
+};
 use structopt::StructOpt;
 
+// Distinguishes some plugins failing to install from strand itself failing, which exits with 1.
+const PLUGINS_FAILED: i32 = 2;
+
+// What `strand outdated` exits with when a sync would change something.
+const UPDATES_AVAILABLE: i32 = 3;
+
+// The version of the JSON output’s schema, to be bumped whenever it changes in a way that could
+// break a script reading it.
+const JSON_VERSION: u32 = 1;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Output {
+    Human,
+    Json,
+}
+
+impl FromStr for Output {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "human" => Ok(Output::Human),
+            "json" => Ok(Output::Json),
+            _ => Err(format!(
+                "unknown output format ‘{}’ -- use ‘human’ or ‘json’",
+                s
+            )),
+        }
+    }
+}
+
 #[derive(StructOpt)]
 struct Opts {
     /// Prints out the config file location
     #[structopt(long)]
     config_location: bool,
 
+    /// Installs exactly what the lockfile records, failing if it disagrees with the config file
+    #[structopt(long)]
+    locked: bool,
+
+    /// Deletes and reinstalls every plugin instead of only those that have changed
+    #[structopt(long)]
+    fresh: bool,
+
+    /// Installs plugins from the download cache without using the network
+    #[structopt(long)]
+    offline: bool,
+
+    /// Installs every plugin it can before reporting any that failed (the default)
+    #[structopt(long, overrides_with = "fail-fast")]
+    keep_going: bool,
+
+    /// Stops at the first plugin that fails to install
+    #[structopt(long, overrides_with = "keep-going")]
+    fail_fast: bool,
+
+    /// How many plugins to download at once, overriding the config file
+    #[structopt(long, short, value_name = "N")]
+    jobs: Option<usize>,
+
+    /// Prints each plugin once it is installed instead of showing what every plugin is doing
+    #[structopt(long, short)]
+    quiet: bool,
+
+    /// Lists the commits each Git plugin gained when it was updated by a sync
+    #[structopt(long)]
+    changes: bool,
+
+    /// Prints results as ‘human’-readable text or as ‘json’ for scripts
+    #[structopt(long, default_value = "human", value_name = "FORMAT")]
+    output: Output,
+
     #[structopt(subcommand)]
     subcommand: Option<Subcommand>,
 }
 
 #[derive(StructOpt)]
 enum Subcommand {
-    /// Install a specific plugin without adding it to the config file
+    /// Install plugins without adding them to the config file, so that the next sync removes them
     #[structopt(name = "install")]
     Install {
-        /// A list of plugins to install and add to the config file
+        /// A list of plugins to install
         // require at least one Plugin in the Vec
         #[structopt(name = "PLUGINS", required = true)]
-        plugins: Vec<Plugin>,
+        plugins: Vec<PluginArg>,
+
+        /// Add the plugins to the config file too, like ‘strand add’
+        #[structopt(long)]
+        save: bool,
+    },
+
+    /// Install plugins and add them to the end of the config file
+    #[structopt(name = "add")]
+    Add {
+        /// A list of plugins to install and add to the config file
+        #[structopt(name = "PLUGINS", required = true)]
+        plugins: Vec<PluginArg>,
+    },
+
+    /// Remove a plugin from the config file and delete it
+    #[structopt(name = "remove")]
+    Remove {
+        /// The plugin’s directory name or its ID, as shown by ‘strand list’
+        #[structopt(name = "PLUGIN")]
+        name: String,
+    },
+
+    /// List installed plugins, along with any in the config file that are not installed and any
+    /// directories strand did not install
+    #[structopt(name = "list")]
+    List,
+
+    /// List the generations of plugins that can be rolled back to
+    #[structopt(name = "generations")]
+    Generations,
+
+    /// Put back an earlier generation of plugins without downloading anything
+    #[structopt(name = "rollback")]
+    Rollback {
+        /// The generation to put back, as shown by ‘strand generations’ (default: the one before
+        /// the current one)
+        #[structopt(name = "GENERATION")]
+        generation: Option<u32>,
+    },
+
+    /// Check which Git plugins have changed upstream without installing anything
+    #[structopt(name = "outdated")]
+    Outdated,
+
+    /// List the commits Git plugins have gained upstream since they were installed
+    #[structopt(name = "changes")]
+    Changes {
+        /// Only list this plugin’s changes, given as its directory name or its ID
+        #[structopt(name = "PLUGIN")]
+        name: Option<String>,
     },
+
+    /// Print the hash of a plugin’s archive to paste into the config file
+    #[structopt(name = "hash")]
+    Hash {
+        /// Print a SHA-512 hash instead of a SHA-256 one
+        #[structopt(long)]
+        sha512: bool,
+
+        /// The plugin to hash
+        #[structopt(name = "PLUGIN")]
+        plugin: Plugin,
+    },
+}
+
+impl Opts {
+    // The name given to the command in JSON output.
+    fn command(&self) -> &'static str {
+        match self.subcommand {
+            _ if self.config_location => "config-location",
+            Some(Subcommand::Install { .. }) => "install",
+            Some(Subcommand::Add { .. }) => "add",
+            Some(Subcommand::Remove { .. }) => "remove",
+            Some(Subcommand::List) => "list",
+            Some(Subcommand::Generations) => "generations",
+            Some(Subcommand::Rollback { .. }) => "rollback",
+            Some(Subcommand::Outdated) => "outdated",
+            Some(Subcommand::Changes { .. }) => "changes",
+            Some(Subcommand::Hash { .. }) => "hash",
+            None => "sync",
+        }
+    }
 }
 
 #[async_std::main]
 async fn main() -> Result<()> {
     let opts = Opts::from_args();
+    let output = opts.output;
+    let command = opts.command();
+
+    // Scripts get errors in the same form as everything else.
+    match run(opts).await {
+        Err(e) if output == Output::Json => {
+            print_json(command, json!({ "error": format!("{:#}", e) }));
+            process::exit(1);
+        }
+        result => result,
+    }
+}
+
+async fn run(opts: Opts) -> Result<()> {
+    let command = opts.command();
 
     let config_dir = strand::get_config_dir();
-    let config_path = config_dir.join("config.yaml");
+    let config_path = strand::find_config_file(&config_dir)?;
 
     // We do this before loading the config file because loading it is not actually needed to
     // display the config file’s location.
     if opts.config_location {
-        println!("{}", config_path.display());
+        match opts.output {
+            Output::Human => println!("{}", config_path.display()),
+            Output::Json => print_json(command, json!({ "path": config_path })),
+        }
+
+        return Ok(());
+    }
+
+    // Hashing a plugin doesn’t need the config file either.
+    if let Some(Subcommand::Hash { sha512, plugin }) = &opts.subcommand {
+        let archive = strand::fetch_archive(plugin, &DownloadOptions::default()).await?;
+
+        let (algorithm, hash) = if *sha512 {
+            ("sha512", strand::sha512_hex(&archive))
+        } else {
+            ("sha256", strand::sha256_hex(&archive))
+        };
+
+        match opts.output {
+            Output::Human => println!("{}: {}", algorithm, hash),
+            Output::Json => print_json(command, json!({ "id": plugin.id(), algorithm: hash })),
+        }
+
         return Ok(());
     }
 
     let config = strand::get_config(&config_path).await?;
 
+    // The lockfile lives next to the config file so that the two can be checked in together.
+    let lockfile_path = config_path.with_file_name("strand.lock");
+
+    if let Some(Subcommand::List) = opts.subcommand {
+        let state = State::read(&config.pack_dir).await?.unwrap_or_default();
+        let entries = strand::list(&config.plugins, &state, &config.pack_dir)?;
+
+        match opts.output {
+            Output::Human => strand::print_list(&entries),
+            Output::Json => print_json(command, json!({ "plugins": entries })),
+        }
+
+        return Ok(());
+    }
+
+    if let Some(Subcommand::Generations) = opts.subcommand {
+        let generations = strand::list_generations(&config.pack_dir).await?;
+
+        match opts.output {
+            Output::Human => strand::print_generations(&generations),
+            Output::Json => print_json(command, json!({ "generations": generations })),
+        }
+
+        return Ok(());
+    }
+
+    if let Some(Subcommand::Rollback { generation }) = opts.subcommand {
+        let stamp = strand::rollback(&config.pack_dir, generation).await?;
+
+        match opts.output {
+            Output::Human => println!("Rolled back to generation {}", stamp.number),
+            Output::Json => print_json(command, json!(stamp)),
+        }
+
+        return Ok(());
+    }
+
+    if let Some(Subcommand::Remove { name }) = &opts.subcommand {
+        let plugin = find_plugin(&config.plugins, name)?;
+        let id = plugin.source.id();
+        let text = read_config_to_edit(&config_path).await?;
+        let edited = strand::remove_plugin(&text, &id)?;
+
+        // Without a state file the next sync clears out the pack directory anyway, but there is no
+        // reason to keep the plugin around until then. Like installing, removing happens in a
+        // copy of the pack directory so that the one in use is kept as an earlier generation.
+        let staging = Staging::new(&config.pack_dir, true).await?;
+
+        let dir = match State::read(staging.path()).await? {
+            Some(mut state) => {
+                let installed = state.plugins.remove(&id);
+                state.write(staging.path()).await?;
+                installed.map(|installed| installed.dir)
+            }
+            None => Some(plugin.install_dir()),
+        };
+
+        if let Some(dir) = dir {
+            let path = staging.path().join(dir);
+
+            if path.exists() {
+                fs::remove_dir_all(&path).await?;
+            }
+        }
+
+        staging.commit(config.generations).await?;
+        strand::write_config(&config_path, &edited).await?;
+
+        if lockfile_path.exists() {
+            let mut lockfile = Lockfile::read(&lockfile_path).await?;
+            lockfile.plugins.remove(&id);
+            lockfile.write(&lockfile_path).await?;
+        }
+
+        match opts.output {
+            Output::Human => println!("Removed {}", id),
+            Output::Json => print_json(command, json!({ "id": id })),
+        }
+
+        return Ok(());
+    }
+
+    let options = InstallOptions {
+        run_timeout: Duration::from_secs(config.run_timeout),
+        cache_dir: strand::get_cache_dir(),
+        offline: opts.offline,
+        fail_fast: opts.fail_fast && !opts.keep_going,
+        jobs: opts.jobs.unwrap_or(config.jobs),
+        jobs_per_host: config.jobs_per_host,
+        progress: match opts.output {
+            Output::Json => ProgressStyle::Silent,
+            Output::Human if !opts.quiet && io::stdout().is_terminal() && !no_color() => {
+                ProgressStyle::Live
+            }
+            Output::Human => ProgressStyle::Lines,
+        },
+        download: DownloadOptions {
+            timeout: Duration::from_secs(config.download_timeout),
+            retries: config.retries,
+        },
+    };
+
+    // Exits with a status that says whether a sync would change anything, so that scripts can
+    // decide whether to run one.
+    if let Some(Subcommand::Outdated) = opts.subcommand {
+        if opts.offline {
+            bail!("cannot check for updates offline");
+        }
+
+        let state = State::read(&config.pack_dir).await?.unwrap_or_default();
+        let entries = strand::outdated(config.plugins, &state, &options).await;
+
+        match opts.output {
+            Output::Human => strand::print_outdated(&entries),
+            Output::Json => print_json(command, json!({ "plugins": entries })),
+        }
+
+        if entries.iter().any(|e| e.status == OutdatedStatus::Failed) {
+            process::exit(PLUGINS_FAILED);
+        } else if entries.iter().any(OutdatedEntry::would_change) {
+            process::exit(UPDATES_AVAILABLE);
+        }
+
+        return Ok(());
+    }
+
+    if let Some(Subcommand::Changes { name }) = &opts.subcommand {
+        if opts.offline {
+            bail!("cannot list changes offline");
+        }
+
+        let plugins = match name {
+            Some(name) => {
+                let id = find_plugin(&config.plugins, name)?.source.id();
+                let plugins = config.plugins.into_iter();
+                plugins.filter(|p| p.source.id() == id).collect()
+            }
+            None => config.plugins,
+        };
+
+        let state = State::read(&config.pack_dir).await?.unwrap_or_default();
+        let entries = strand::outdated(plugins, &state, &options).await;
+        let failed: Vec<_> = entries
+            .iter()
+            .filter(|e| e.status == OutdatedStatus::Failed)
+            .collect();
+
+        let updates = entries
+            .iter()
+            .filter(|e| e.status == OutdatedStatus::Outdated)
+            .filter_map(|e| {
+                Some(Update {
+                    plugin: e.source.clone(),
+                    from: e.installed.clone()?,
+                    to: e.latest.clone()?,
+                })
+            })
+            .collect();
+        let changes = strand::changes(updates, &options).await;
+
+        match opts.output {
+            Output::Human => {
+                for entry in &failed {
+                    let error = entry.error.as_deref().unwrap_or_default();
+                    eprintln!("failed to check {}: {}", entry.id, error);
+                }
+
+                if changes.is_empty() && failed.is_empty() {
+                    println!("All plugins are up to date");
+                }
+
+                strand::print_changes(&changes);
+            }
+            Output::Json => {
+                let failed: Vec<_> = failed
+                    .iter()
+                    .map(|e| json!({ "id": e.id, "error": e.error }))
+                    .collect();
+
+                print_json(command, json!({ "plugins": changes, "failed": failed }));
+            }
+        }
+
+        if !failed.is_empty() || changes.iter().any(|c| c.error.is_some()) {
+            process::exit(PLUGINS_FAILED);
+        }
+
+        return Ok(());
+    }
+
     // Install all plugins specified by the install subcommand.
-    if let Some(Subcommand::Install { plugins }) = opts.subcommand {
-        strand::install_plugins(plugins, config.plugin_dir).await?;
+    let to_install = match opts.subcommand {
+        Some(Subcommand::Install { plugins, save }) => Some((plugins, save)),
+        Some(Subcommand::Add { plugins }) => Some((plugins, true)),
+        _ => None,
+    };
+
+    // Install all plugins specified by the install or add subcommands.
+    if let Some((plugins, save)) = to_install {
+        // Any problem with editing the config file is found before anything is installed, but the
+        // edit is only saved once everything has been.
+        let edited = if save {
+            let text = read_config_to_edit(&config_path).await?;
+            Some(strand::add_plugins(&text, &config, &plugins)?)
+        } else {
+            None
+        };
+
+        let plugins = plugins.into_iter().map(|arg| arg.plugin.into()).collect();
+
+        let state = State::read(&config.pack_dir).await?;
+        let staging = Staging::new(&config.pack_dir, true).await?;
+        let (staging, report) = install_staged(
+            staging,
+            plugins,
+            None,
+            state.as_ref().unwrap_or(&State::default()),
+            &options,
+            opts.output,
+            command,
+        )
+        .await?;
+
+        // Record these plugins in the state file so that the next sync knows about them. If there
+        // is no state file the next sync clears out the pack directory anyway.
+        if let Some(mut state) = state {
+            state.plugins.extend(report.state.plugins.clone());
+            state.write(staging.path()).await?;
+        }
+
+        staging.commit(config.generations).await?;
+
+        if let Some(edited) = edited {
+            strand::write_config(&config_path, &edited).await?;
+
+            // Keep the lockfile in step with the config file, so that --locked still works.
+            if lockfile_path.exists() {
+                let mut lockfile = Lockfile::read(&lockfile_path).await?;
+                lockfile
+                    .plugins
+                    .extend(Lockfile::from(&report.state).plugins);
+                lockfile.write(&lockfile_path).await?;
+            }
+        }
+
+        print_report(opts.output, command, &report, &[], None);
+
         return Ok(()); // Early return since we don’t need to install plugins from the config file.
     }
 
-    // Clean out the plugin directory before installing.
-    ensure_empty_dir(&config.plugin_dir).await?;
-    strand::install_plugins(config.plugins, config.plugin_dir).await?;
+    let ids: Vec<_> = config.plugins.iter().map(|p| p.source.id()).collect();
+
+    // Offline, the lockfile is the only way to know which commits to install, so it is used even
+    // without --locked for whichever plugins it mentions.
+    let lockfile = if opts.locked {
+        let lockfile = Lockfile::read(&lockfile_path).await?;
+        lockfile.check_matches(&ids)?;
+
+        Some(lockfile)
+    } else if opts.offline && lockfile_path.exists() {
+        Some(Lockfile::read(&lockfile_path).await?)
+    } else {
+        None
+    };
+
+    // Without a record of what is installed we cannot tell what is safe to keep, so start over
+    // from an empty pack directory.
+    let state = match State::read(&config.pack_dir).await? {
+        Some(state) if !opts.fresh => Some(state),
+        _ => None,
+    };
+
+    let staging = Staging::new(&config.pack_dir, state.is_some()).await?;
+    let mut state = state.unwrap_or_default();
+
+    // Removing plugins first frees up their directories for any new plugins that want them.
+    let removed = state.remove_dropped(&ids, staging.path()).await?;
+
+    // The plugins themselves are handed over to be installed, so --changes keeps its own copy.
+    let sources: Vec<_> = config.plugins.iter().map(|p| p.source.clone()).collect();
+
+    let (staging, report) = install_staged(
+        staging,
+        config.plugins,
+        lockfile.as_ref(),
+        &state,
+        &options,
+        opts.output,
+        command,
+    )
+    .await?;
+
+    report.state.write(staging.path()).await?;
+    staging.commit(config.generations).await?;
+    Lockfile::from(&report.state).write(&lockfile_path).await?;
+
+    // Plugins whose commit changed are compared with the commit they were at before.
+    let changes = if opts.changes {
+        let updates = report
+            .plugins
+            .iter()
+            .filter(|p| matches!(p.outcome, Outcome::Installed))
+            .filter_map(|p| {
+                let plugin = sources.iter().find(|source| source.id() == p.id)?.clone();
+                let from = state.plugins.get(&p.id)?.version.commit.clone()?;
+                let to = report.state.plugins.get(&p.id)?.version.commit.clone()?;
+
+                if from == to {
+                    return None;
+                }
+
+                Some(Update { plugin, from, to })
+            })
+            .collect();
+
+        Some(strand::changes(updates, &options).await)
+    } else {
+        None
+    };
+
+    print_report(opts.output, command, &report, &removed, changes.as_deref());
 
     Ok(())
 }
 
-async fn remove_path(path: &Path) -> Result<()> {
-    if fs::metadata(path).await?.is_dir() {
-        fs::remove_dir_all(path).await?;
-    } else {
-        fs::remove_file(path).await?;
+// See https://no-color.org.
+fn no_color() -> bool {
+    std::env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty())
+}
+
+// Every JSON document says which version of the schema it follows and which command produced it.
+fn print_json(command: &str, mut document: serde_json::Value) {
+    if let Some(fields) = document.as_object_mut() {
+        fields.insert("version".into(), JSON_VERSION.into());
+        fields.insert("command".into(), command.into());
     }
 
-    Ok(())
+    println!("{}", document);
 }
 
-async fn ensure_empty_dir(path: &Path) -> Result<()> {
-    if path.exists() {
-        remove_path(path).await?;
+// People only need to hear about failures and removed plugins, having watched everything else
+// happen; scripts get every plugin. Nothing is removed until the pack directory is replaced, so
+// `removed` is only given once it has been.
+fn print_report(
+    output: Output,
+    command: &str,
+    report: &Report,
+    removed: &[String],
+    changes: Option<&[PluginChanges]>,
+) {
+    match output {
+        Output::Human => {
+            for id in removed {
+                println!("Removed {}", id);
+            }
+
+            if report.failed() > 0 {
+                report.print_summary();
+            }
+
+            if let Some(changes) = changes {
+                strand::print_changes(changes);
+            }
+        }
+        Output::Json => {
+            let mut document = json!({
+                "plugins": report.results(),
+                "failed": report.failed(),
+                "removed": removed,
+            });
+
+            if let Some(changes) = changes {
+                document["changes"] = json!(changes);
+            }
+
+            print_json(command, document);
+        }
     }
+}
 
-    fs::create_dir_all(path).await?;
+// Edits are made to the config file’s text so as to keep its comments and layout, which only
+// works for YAML.
+async fn read_config_to_edit(config_path: &Path) -> Result<String> {
+    if ConfigFormat::from_path(config_path)? != ConfigFormat::Yaml {
+        bail!(
+            "strand can only edit YAML config files -- make the change to {} by hand",
+            config_path.display()
+        );
+    }
 
-    Ok(())
+    Ok(fs::read_to_string(config_path).await?)
+}
+
+// Finds the plugin in the config file with the given directory name or ID.
+fn find_plugin<'a>(plugins: &'a [PluginSpec], name: &str) -> Result<&'a PluginSpec> {
+    let matches: Vec<_> = plugins
+        .iter()
+        .filter(|p| p.source.id() == *name || p.dir_name() == *name)
+        .collect();
+
+    match matches.as_slice() {
+        [plugin] => Ok(plugin),
+        [] => bail!("no plugin in the config file is called ‘{}’", name),
+        _ => bail!(
+            "more than one plugin is called ‘{}’ -- use its ID from ‘strand list’ instead",
+            name
+        ),
+    }
+}
+
+// Installs plugins into the staging directory, returning a report on them if they all succeeded.
+// If any failed the staging directory is thrown away, leaving the pack directory untouched.
+async fn install_staged(
+    staging: Staging,
+    plugins: Vec<PluginSpec>,
+    lockfile: Option<&Lockfile>,
+    state: &State,
+    options: &InstallOptions,
+    output: Output,
+    command: &str,
+) -> Result<(Staging, Report)> {
+    let result =
+        strand::install_plugins(plugins, staging.path().into(), lockfile, state, options).await;
+
+    match result {
+        Ok(report) if report.failed() == 0 => Ok((staging, report)),
+        Ok(mut report) => {
+            discard(staging).await;
+            report.discard();
+            print_report(output, command, &report, &[], None);
+            process::exit(PLUGINS_FAILED);
+        }
+        Err(e) => {
+            discard(staging).await;
+            Err(e)
+        }
+    }
+}
+
+// Failing to clean up only merits a warning, so that it never hides why the staging directory was
+// being thrown away. Whatever is left is cleared out by the next run.
+async fn discard(staging: Staging) {
+    let path = staging.path().to_path_buf();
+
+    if let Err(e) = staging.discard().await {
+        eprintln!("Warning: failed to remove {} -- {:#}", path.display(), e);
+    }
 }
@@ -0,0 +1,309 @@
+//! Checking Git plugins for upstream changes without installing anything, for `strand outdated`.
+
+use crate::{
+    clone, limit, list::print_table, remote::RemoteRefs, InstallOptions, Plugin, PluginSpec, State,
+};
+use anyhow::Result;
+use async_std::task;
+use serde::Serialize;
+
+#[derive(Serialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum OutdatedStatus {
+    /// Installed at the commit its reference points to now.
+    UpToDate,
+    /// Its reference points to a different commit now, so the next sync would update it.
+    Outdated,
+    /// In the config file but not installed yet, so the next sync would install it.
+    NotInstalled,
+    /// Its reference could not be resolved.
+    Failed,
+}
+
+/// A row of `strand outdated`.
+#[derive(Serialize)]
+pub struct OutdatedEntry {
+    pub id: String,
+    pub status: OutdatedStatus,
+    /// The reference that was checked, which is the repo’s default branch if none is given.
+    #[serde(rename = "ref")]
+    pub git_ref: Option<String>,
+    /// The commit the plugin is installed at.
+    pub installed: Option<String>,
+    /// The commit its reference points to now.
+    pub latest: Option<String>,
+    /// A newer tag than the one the plugin is installed from, if it was installed from a tag.
+    /// Syncing does not move to it by itself, so this does not make the plugin outdated.
+    pub newer_tag: Option<String>,
+    pub error: Option<String>,
+    /// The plugin that was checked.
+    #[serde(skip)]
+    pub source: Plugin,
+}
+
+impl OutdatedEntry {
+    /// Whether the next sync would change the plugin.
+    pub fn would_change(&self) -> bool {
+        matches!(
+            self.status,
+            OutdatedStatus::Outdated | OutdatedStatus::NotInstalled
+        )
+    }
+}
+
+// Lists what the repo has now, or nothing for archives, which have no way of telling.
+async fn remote_refs(
+    plugin: &Plugin,
+    options: &InstallOptions,
+) -> Result<Option<(String, RemoteRefs)>> {
+    match plugin {
+        Plugin::Git(repo) => {
+            let url = repo.repo_url();
+            let refs = RemoteRefs::fetch(&url, &options.download).await?;
+            Ok(Some((url, refs)))
+        }
+        Plugin::GitClone(repo) => {
+            let refs = clone::remote_refs(&repo.url, options.download.timeout).await?;
+            Ok(Some((repo.url.clone(), refs)))
+        }
+        Plugin::Archive(_) => Ok(None),
+    }
+}
+
+// Returns the reference that was checked, the commit it points to and any newer tag.
+async fn check_plugin(
+    plugin: &Plugin,
+    options: &InstallOptions,
+) -> Result<Option<(String, String, Option<String>)>> {
+    let (url, refs) = match remote_refs(plugin, options).await? {
+        Some(remote) => remote,
+        None => return Ok(None),
+    };
+
+    let git_ref = match plugin {
+        Plugin::Git(repo) => repo.git_ref.as_deref(),
+        Plugin::GitClone(repo) => repo.git_ref.as_deref(),
+        Plugin::Archive(_) => None,
+    };
+
+    let (git_ref, commit) = refs.resolve_or_default(&url, git_ref)?;
+    let newer_tag = refs
+        .newest_tag(&git_ref)
+        .filter(|tag| *tag != git_ref)
+        .map(String::from);
+
+    Ok(Some((git_ref, commit, newer_tag)))
+}
+
+/// Resolves every Git plugin’s reference and compares it with the commit it is installed at, in
+/// the order of the config file. Archives are left out.
+pub async fn outdated(
+    plugins: Vec<PluginSpec>,
+    state: &State,
+    options: &InstallOptions,
+) -> Vec<OutdatedEntry> {
+    let limiter = limit::Limiter::new(options.jobs, options.jobs_per_host);
+
+    let tasks: Vec<_> = plugins
+        .into_iter()
+        .map(|p| {
+            let options = options.clone();
+            let limiter = limiter.clone();
+            let id = p.source.id();
+            let installed = state
+                .plugins
+                .get(&id)
+                .map(|installed| installed.version.commit.clone());
+
+            task::spawn(async move {
+                let _permit = limiter.acquire(&p.source.host()).await;
+                let result = check_plugin(&p.source, &options).await;
+                (id, p.source, installed, result)
+            })
+        })
+        .collect();
+
+    let mut entries = Vec::new();
+
+    for task in tasks {
+        let (id, source, installed, result) = task.await;
+
+        let entry = match result {
+            Ok(None) => continue,
+            Ok(Some((git_ref, latest, newer_tag))) => {
+                let status = match &installed {
+                    None => OutdatedStatus::NotInstalled,
+                    Some(commit) if commit.as_ref() == Some(&latest) => OutdatedStatus::UpToDate,
+                    Some(_) => OutdatedStatus::Outdated,
+                };
+
+                OutdatedEntry {
+                    id,
+                    status,
+                    git_ref: Some(git_ref),
+                    installed: installed.flatten(),
+                    latest: Some(latest),
+                    newer_tag,
+                    error: None,
+                    source,
+                }
+            }
+            Err(e) => OutdatedEntry {
+                id,
+                status: OutdatedStatus::Failed,
+                git_ref: None,
+                installed: installed.flatten(),
+                latest: None,
+                newer_tag: None,
+                error: Some(format!("{:#}", e)),
+                source,
+            },
+        };
+
+        entries.push(entry);
+    }
+
+    entries
+}
+
+/// Prints the plugins that would change or that have a newer tag, one per line.
+pub fn print_outdated(entries: &[OutdatedEntry]) {
+    let rows: Vec<_> = entries
+        .iter()
+        .filter(|entry| entry.status != OutdatedStatus::UpToDate || entry.newer_tag.is_some())
+        .map(|entry| {
+            let commit = |commit: &Option<String>| match commit {
+                Some(commit) => format!("{:.7}", commit),
+                None => String::new(),
+            };
+
+            let note = match (&entry.status, &entry.newer_tag, &entry.error) {
+                (OutdatedStatus::Failed, _, Some(error)) => {
+                    format!("failed: {}", error.lines().next().unwrap_or_default())
+                }
+                (OutdatedStatus::NotInstalled, ..) => "not installed".into(),
+                (_, Some(tag), _) => format!("{} is available", tag),
+                _ => String::new(),
+            };
+
+            vec![
+                entry.id.clone(),
+                entry.git_ref.clone().unwrap_or_default(),
+                commit(&entry.installed),
+                commit(&entry.latest),
+                note,
+            ]
+        })
+        .collect();
+
+    if rows.is_empty() {
+        println!("All plugins are up to date");
+        return;
+    }
+
+    let header = ["PLUGIN", "REF", "INSTALLED", "LATEST", ""]
+        .map(String::from)
+        .to_vec();
+    print_table(&[vec![header], rows].concat());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test_server, DownloadOptions, InstalledPlugin, LockedPlugin, ProgressStyle};
+    use std::time::Duration;
+
+    fn pkt_line(s: &str) -> String {
+        format!("{:04x}{}", s.len() + 4, s)
+    }
+
+    #[async_std::test]
+    async fn test_outdated() {
+        let advertisement = [
+            pkt_line("# service=git-upload-pack\n"),
+            "0000".into(),
+            pkt_line(
+                "1111111111111111111111111111111111111111 HEAD\0symref=HEAD:refs/heads/main\n",
+            ),
+            pkt_line("1111111111111111111111111111111111111111 refs/heads/main\n"),
+            pkt_line("2222222222222222222222222222222222222222 refs/tags/v1.0\n"),
+            pkt_line("3333333333333333333333333333333333333333 refs/tags/v1.1\n"),
+            "0000".into(),
+        ]
+        .concat();
+
+        let server = test_server::serve(move |path| match path {
+            "/tpope/vim-surround.git/info/refs?service=git-upload-pack" => {
+                test_server::Response::ok(advertisement.clone())
+            }
+            _ => test_server::Response::not_found(),
+        });
+
+        let installed = |commit: &str| InstalledPlugin {
+            source: String::new(),
+            git_ref: None,
+            version: LockedPlugin {
+                commit: Some(commit.into()),
+                sha256: None,
+                sha512: None,
+            },
+            dir: "start/vim-surround".into(),
+            run: None,
+            format: None,
+            installed_at: None,
+        };
+
+        let ids = [
+            format!("gitea({})@tpope/vim-surround", server),
+            format!("gitea({})@tpope/vim-surround:v1.0", server),
+            format!("gitea({})@tpope/vim-surround:v1.1", server),
+            format!("gitea({})@tpope/vim-missing", server),
+            "https://example.com/vim-qlist.tar.gz".into(),
+        ];
+
+        let mut state = State::default();
+        state.plugins.extend(vec![
+            (ids[0].clone(), installed("0000000")),
+            (
+                ids[1].clone(),
+                installed("2222222222222222222222222222222222222222"),
+            ),
+        ]);
+
+        let plugins = ids
+            .iter()
+            .map(|id| id.parse::<Plugin>().unwrap().into())
+            .collect();
+        let options = InstallOptions {
+            run_timeout: Duration::from_secs(10),
+            cache_dir: std::env::temp_dir(),
+            offline: false,
+            fail_fast: false,
+            jobs: 8,
+            jobs_per_host: 4,
+            progress: ProgressStyle::Silent,
+            download: DownloadOptions::default(),
+        };
+
+        let entries = outdated(plugins, &state, &options).await;
+        let rows: Vec<_> = entries
+            .iter()
+            .map(|e| (e.git_ref.as_deref(), e.status, e.newer_tag.as_deref()))
+            .collect();
+
+        assert_eq!(
+            rows,
+            [
+                (Some("main"), OutdatedStatus::Outdated, None),
+                (Some("v1.0"), OutdatedStatus::UpToDate, Some("v1.1")),
+                (Some("v1.1"), OutdatedStatus::NotInstalled, None),
+                (None, OutdatedStatus::Failed, None),
+            ]
+        );
+        assert_eq!(
+            entries[0].latest.as_deref(),
+            Some("1111111111111111111111111111111111111111")
+        );
+        assert!(entries[3].error.as_ref().unwrap().contains("404"));
+    }
+}
@@ -0,0 +1,347 @@
+//! Showing what each plugin is doing while they are installed.
+
+use async_std::task;
+use std::{
+    fmt::Write as _,
+    io::{self, Write as _},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+// More lines than this would risk scrolling the top of the view off the terminal, which would stop
+// it from being redrawn in place.
+const MAX_LINES: usize = 10;
+
+// Downloads report every chunk they receive, which is far more often than is worth redrawing for.
+const REDRAW_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum ProgressStyle {
+    /// Every plugin being worked on gets a line that is kept up to date, for terminals.
+    Live,
+    /// Only finished plugins are printed, one per line.
+    Lines,
+    /// Nothing is printed, e.g. because the output is meant for another program.
+    Silent,
+}
+
+pub enum Stage {
+    Resolving,
+    Downloading { bytes: u64, total: Option<u64> },
+    Extracting,
+    Building,
+}
+
+struct Entry {
+    key: usize,
+    id: String,
+    stage: Stage,
+    started: Instant,
+}
+
+#[derive(Default)]
+struct View {
+    next_key: usize,
+    entries: Vec<Entry>,
+    drawn: usize,
+    last_drawn: Option<Instant>,
+    closed: bool,
+}
+
+#[derive(Clone)]
+pub struct Progress {
+    style: ProgressStyle,
+    view: Arc<Mutex<View>>,
+}
+
+/// A plugin’s line in the progress view, which disappears when it is dropped.
+pub struct Line {
+    key: usize,
+    progress: Progress,
+}
+
+impl Progress {
+    /// A live view also redraws itself every so often, so that a stalled plugin’s time keeps going
+    /// up.
+    pub fn new(style: ProgressStyle) -> Self {
+        let progress = Self {
+            style,
+            view: Default::default(),
+        };
+
+        if progress.is_live() {
+            let view = progress.view.clone();
+
+            task::spawn(async move {
+                loop {
+                    task::sleep(Duration::from_secs(1)).await;
+
+                    let mut view = view.lock().unwrap();
+
+                    if view.closed {
+                        break;
+                    }
+
+                    draw(&mut view, None);
+                }
+            });
+        }
+
+        progress
+    }
+
+    pub fn start(&self, id: String) -> Line {
+        let mut view = self.view.lock().unwrap();
+        let key = view.next_key;
+
+        view.next_key += 1;
+        view.entries.push(Entry {
+            key,
+            id,
+            stage: Stage::Resolving,
+            started: Instant::now(),
+        });
+
+        if self.is_live() {
+            draw(&mut view, None);
+        }
+
+        Line {
+            key,
+            progress: self.clone(),
+        }
+    }
+
+    fn is_live(&self) -> bool {
+        self.style == ProgressStyle::Live
+    }
+
+    /// Clears the view away so that whatever is printed next starts on a clean line.
+    pub fn close(&self) {
+        let mut view = self.view.lock().unwrap();
+        view.closed = true;
+
+        if self.is_live() {
+            draw(&mut view, None);
+        }
+    }
+}
+
+impl Line {
+    pub fn set(&self, stage: Stage) {
+        let mut view = self.progress.view.lock().unwrap();
+        let throttle = matches!(stage, Stage::Downloading { .. });
+
+        if let Some(entry) = view.entries.iter_mut().find(|e| e.key == self.key) {
+            entry.stage = stage;
+        }
+
+        let due = view
+            .last_drawn
+            .is_none_or(|last| last.elapsed() >= REDRAW_INTERVAL);
+
+        if self.progress.is_live() && (due || !throttle) {
+            draw(&mut view, None);
+        }
+    }
+
+    /// Prints a warning about the plugin above the view, where it is not drawn over. Without a live
+    /// view it goes to stderr like any other warning.
+    pub fn warn(&self, message: &str) {
+        let mut view = self.progress.view.lock().unwrap();
+
+        match self.progress.style {
+            ProgressStyle::Live => draw(&mut view, Some(message)),
+            _ => eprintln!("{}", message),
+        }
+    }
+
+    /// Prints a message saying how the plugin’s installation ended, in place of its line.
+    pub fn finish(self, message: &str) {
+        let mut view = self.progress.view.lock().unwrap();
+        view.entries.retain(|e| e.key != self.key);
+
+        match self.progress.style {
+            ProgressStyle::Live => draw(&mut view, Some(message)),
+            ProgressStyle::Lines => println!("{}", message),
+            ProgressStyle::Silent => {}
+        }
+    }
+}
+
+impl Drop for Line {
+    fn drop(&mut self) {
+        let mut view = self.progress.view.lock().unwrap();
+        let len = view.entries.len();
+        view.entries.retain(|e| e.key != self.key);
+
+        if self.progress.is_live() && view.entries.len() != len {
+            draw(&mut view, None);
+        }
+    }
+}
+
+// Redraws the view over the top of its last drawing, printing `above` above it for good.
+fn draw(view: &mut View, above: Option<&str>) {
+    let mut out = String::new();
+
+    // Move to the start of the first line drawn last time and clear everything after it.
+    if view.drawn > 0 {
+        let _ = write!(out, "\x1b[{}F", view.drawn);
+    }
+    out.push_str("\x1b[J");
+
+    if let Some(above) = above {
+        out.push_str(above);
+        out.push('\n');
+    }
+
+    let lines = if view.closed {
+        Vec::new()
+    } else {
+        render(&view.entries, terminal_width())
+    };
+
+    for line in &lines {
+        out.push_str(line);
+        out.push('\n');
+    }
+
+    view.drawn = lines.len();
+    view.last_drawn = Some(Instant::now());
+
+    let mut stdout = io::stdout();
+    let _ = stdout.write_all(out.as_bytes());
+    let _ = stdout.flush();
+}
+
+// Lines that wrap would throw off how far back up the view has to go to redraw itself, so they
+// are cut off at the terminal’s width. Shells rarely export `$COLUMNS`, so the terminal is asked
+// first.
+fn terminal_width() -> usize {
+    tty_width()
+        .or_else(|| {
+            std::env::var("COLUMNS")
+                .ok()
+                .and_then(|columns| columns.parse().ok())
+        })
+        .unwrap_or(80)
+}
+
+#[cfg(unix)]
+fn tty_width() -> Option<usize> {
+    // Fails if stdout is not a terminal.
+    let size = rustix::termios::tcgetwinsize(io::stdout()).ok()?;
+
+    match size.ws_col {
+        0 => None,
+        columns => Some(columns.into()),
+    }
+}
+
+#[cfg(not(unix))]
+fn tty_width() -> Option<usize> {
+    None
+}
+
+fn render(entries: &[Entry], width: usize) -> Vec<String> {
+    let id_width = entries.iter().map(|e| e.id.chars().count()).max();
+    let id_width = id_width.unwrap_or_default().min(width / 2);
+
+    let mut lines: Vec<_> = entries
+        .iter()
+        .take(MAX_LINES)
+        .map(|entry| {
+            let stage = match entry.stage {
+                Stage::Resolving => "resolving".into(),
+                Stage::Downloading { bytes: 0, .. } => "downloading".into(),
+                Stage::Downloading {
+                    bytes,
+                    total: Some(total),
+                } => format!("downloading {} / {}", size(bytes), size(total)),
+                Stage::Downloading { bytes, total: None } => {
+                    format!("downloading {}", size(bytes))
+                }
+                Stage::Extracting => "extracting".into(),
+                Stage::Building => "building".into(),
+            };
+
+            let line = format!(
+                "{:>4}s  {:width$}  {}",
+                entry.started.elapsed().as_secs(),
+                entry.id,
+                stage,
+                width = id_width
+            );
+
+            line.chars().take(width.saturating_sub(1)).collect()
+        })
+        .collect();
+
+    if entries.len() > MAX_LINES {
+        lines.push(format!("       … and {} more", entries.len() - MAX_LINES));
+    }
+
+    lines
+}
+
+pub fn size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+
+    let mut size = bytes as f64;
+    let mut unit = 0;
+
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    match unit {
+        0 => format!("{} B", bytes),
+        _ => format!("{:.1} {}", size, UNITS[unit]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        assert_eq!(size(12), "12 B");
+        assert_eq!(size(1536), "1.5 KiB");
+        assert_eq!(size(3 * 1024 * 1024), "3.0 MiB");
+
+        let entry = |id: &str, stage| Entry {
+            key: 0,
+            id: id.into(),
+            stage,
+            started: Instant::now(),
+        };
+
+        let entries = vec![
+            entry(
+                "github@tpope/vim-surround",
+                Stage::Downloading {
+                    bytes: 1024,
+                    total: Some(2048),
+                },
+            ),
+            entry("github@junegunn/fzf", Stage::Building),
+        ];
+
+        assert_eq!(
+            render(&entries, 80),
+            [
+                "   0s  github@tpope/vim-surround  downloading 1.0 KiB / 2.0 KiB",
+                "   0s  github@junegunn/fzf        building",
+            ]
+        );
+        assert_eq!(render(&entries, 20)[0], "   0s  github@tpope");
+
+        let many: Vec<_> = (0..12)
+            .map(|i| entry(&i.to_string(), Stage::Resolving))
+            .collect();
+        assert_eq!(render(&many, 80).last().unwrap(), "       … and 2 more");
+    }
+}
@@ -0,0 +1,302 @@
+use crate::{download, DownloadOptions};
+use anyhow::{anyhow, bail, Result};
+
+/// The references a Git repository advertises over the smart HTTP protocol, as returned by
+/// `GET <repo>/info/refs?service=git-upload-pack`. This lets us turn branch and tag names into
+/// commit hashes without cloning anything.
+pub struct RemoteRefs {
+    head: Option<String>,
+    refs: Vec<(String, String)>,
+}
+
+impl RemoteRefs {
+    pub async fn fetch(repo_url: &str, options: &DownloadOptions) -> Result<Self> {
+        let url = format!("{}/info/refs?service=git-upload-pack", repo_url);
+        let body = download::get(&url, options, &|_, _| ()).await?.body;
+
+        Self::parse(&body)
+    }
+
+    fn parse(mut bytes: &[u8]) -> Result<Self> {
+        let mut head = None;
+        let mut refs = Vec::new();
+
+        // The advertisement is a series of ‘pkt-lines’, each of which is prefixed by its length
+        // (including the prefix itself) as four hex digits. A length of zero is a flush packet.
+        while !bytes.is_empty() {
+            if bytes.len() < 4 {
+                bail!("truncated ref advertisement");
+            }
+
+            let len = std::str::from_utf8(&bytes[..4])
+                .ok()
+                .and_then(|len| usize::from_str_radix(len, 16).ok())
+                .ok_or_else(|| anyhow!("malformed ref advertisement -- is this a Git repo?"))?;
+
+            if len == 0 {
+                bytes = &bytes[4..];
+                continue;
+            }
+
+            if len < 4 || len > bytes.len() {
+                bail!("truncated ref advertisement");
+            }
+
+            let line = String::from_utf8_lossy(&bytes[4..len]);
+            bytes = &bytes[len..];
+
+            // The service announcement is not a ref.
+            if line.starts_with('#') {
+                continue;
+            }
+
+            // Capabilities are only sent after the first ref, separated from it by a NUL byte.
+            // Among them is the branch HEAD points to, e.g. ‘symref=HEAD:refs/heads/main’.
+            let line = line.trim_end_matches('\n');
+            let mut parts = line.split('\0');
+            let line = parts.next().unwrap_or_default();
+
+            if let Some(capabilities) = parts.next() {
+                head = capabilities
+                    .split(' ')
+                    .find_map(|c| c.strip_prefix("symref=HEAD:"))
+                    .map(String::from);
+            }
+
+            if let Some(i) = line.find(' ') {
+                refs.push((line[i + 1..].to_string(), line[..i].to_string()));
+            }
+        }
+
+        Ok(Self { head, refs })
+    }
+
+    /// Parses the output of `git ls-remote --symref`, for repos that are not served over HTTP.
+    pub fn parse_ls_remote(output: &str) -> Self {
+        let mut head = None;
+        let mut refs = Vec::new();
+
+        for line in output.lines() {
+            let mut parts = line.splitn(2, '\t');
+            let (first, name) = match (parts.next(), parts.next()) {
+                (Some(first), Some(name)) => (first, name),
+                _ => continue,
+            };
+
+            match first.strip_prefix("ref: ") {
+                Some(target) if name == "HEAD" => head = Some(target.into()),
+                Some(_) => {}
+                None => refs.push((name.into(), first.into())),
+            }
+        }
+
+        Self { head, refs }
+    }
+
+    fn get(&self, name: &str) -> Option<&str> {
+        self.refs
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, hash)| hash.as_str())
+    }
+
+    /// Returns the name of the branch HEAD points to. Servers too old to advertise this are
+    /// handled by looking for a branch at the same commit as HEAD.
+    pub fn default_branch(&self) -> Option<&str> {
+        let head = match &self.head {
+            Some(head) => head.as_str(),
+            None => {
+                let commit = self.get("HEAD")?;
+                self.refs
+                    .iter()
+                    .find(|(name, hash)| name.starts_with("refs/heads/") && hash == commit)
+                    .map(|(name, _)| name.as_str())?
+            }
+        };
+
+        head.strip_prefix("refs/heads/")
+    }
+
+    /// Returns the commit hash a branch name, tag name or full ref points to. Annotated tags are
+    /// peeled to the commit they tag. Anything that looks like a commit hash is passed through
+    /// unchanged, since servers do not advertise individual commits.
+    pub fn resolve(&self, git_ref: &str) -> Option<String> {
+        let branch = format!("refs/heads/{}", git_ref);
+        let tag = format!("refs/tags/{}", git_ref);
+        let peeled_tag = format!("{}^{{}}", tag);
+
+        self.get(git_ref)
+            .or_else(|| self.get(&branch))
+            .or_else(|| self.get(&peeled_tag))
+            .or_else(|| self.get(&tag))
+            .map(String::from)
+            .or_else(|| {
+                if is_commit_hash(git_ref) {
+                    Some(git_ref.into())
+                } else {
+                    None
+                }
+            })
+    }
+
+    /// Returns the newest tag named like the given one going by the numbers in their names, e.g.
+    /// ‘v1.10’ for ‘v1.9’. Tags with anything else in their names, such as release candidates, are
+    /// left out, and nothing is returned if the given reference is not such a tag.
+    pub fn newest_tag(&self, tag: &str) -> Option<&str> {
+        let (prefix, _) = version_key(tag)?;
+        self.get(&format!("refs/tags/{}", tag))?;
+
+        self.refs
+            .iter()
+            .filter_map(|(name, _)| name.strip_prefix("refs/tags/"))
+            .filter_map(|name| Some((version_key(name)?, name)))
+            .filter(|((other_prefix, _), _)| *other_prefix == prefix)
+            .max_by(|((_, a), _), ((_, b), _)| a.cmp(b))
+            .map(|(_, name)| name)
+    }
+
+    /// Resolves a Git reference to a commit hash like `resolve`, using the repo’s default branch
+    /// when no reference is given. Returns the reference that was used along with the commit;
+    /// `repo_url` is only used to say which repo an error is about.
+    pub fn resolve_or_default(
+        &self,
+        repo_url: &str,
+        git_ref: Option<&str>,
+    ) -> Result<(String, String)> {
+        let git_ref = match git_ref {
+            Some(git_ref) => git_ref,
+            None => self
+                .default_branch()
+                .ok_or_else(|| anyhow!("could not determine the default branch of {}", repo_url))?,
+        };
+
+        let commit = self
+            .resolve(git_ref)
+            .ok_or_else(|| anyhow!("could not find Git reference ‘{}’ in {}", git_ref, repo_url))?;
+
+        Ok((git_ref.into(), commit))
+    }
+}
+
+/// `RemoteRefs::resolve_or_default` for the repo at the given URL, asking it for its references
+/// over HTTP.
+pub async fn resolve(
+    repo_url: &str,
+    git_ref: Option<&str>,
+    options: &DownloadOptions,
+) -> Result<(String, String)> {
+    RemoteRefs::fetch(repo_url, options)
+        .await?
+        .resolve_or_default(repo_url, git_ref)
+}
+
+// Splits a tag such as ‘v1.2.3’ into whatever comes before its first digit and the numbers after it.
+fn version_key(tag: &str) -> Option<(&str, Vec<u64>)> {
+    let start = tag.find(|c: char| c.is_ascii_digit())?;
+    let numbers = tag[start..]
+        .split('.')
+        .map(|n| n.parse().ok())
+        .collect::<Option<Vec<u64>>>()?;
+
+    Some((&tag[..start], numbers))
+}
+
+pub fn is_commit_hash(s: &str) -> bool {
+    s.len() >= 7 && s.len() <= 40 && s.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server;
+
+    fn pkt_line(s: &str) -> String {
+        format!("{:04x}{}", s.len() + 4, s)
+    }
+
+    #[test]
+    fn test_resolve_advertised_refs() {
+        let advertisement = [
+            pkt_line("# service=git-upload-pack\n"),
+            "0000".into(),
+            pkt_line("1111111111111111111111111111111111111111 HEAD\0multi_ack symref=HEAD:refs/heads/main\n"),
+            pkt_line("1111111111111111111111111111111111111111 refs/heads/main\n"),
+            pkt_line("2222222222222222222222222222222222222222 refs/tags/v1.0\n"),
+            pkt_line("3333333333333333333333333333333333333333 refs/tags/v1.0^{}\n"),
+            "0000".into(),
+        ]
+        .concat();
+
+        let refs = RemoteRefs::parse(advertisement.as_bytes()).unwrap();
+
+        assert_eq!(refs.default_branch(), Some("main"));
+        assert_eq!(
+            refs.resolve("main").as_deref(),
+            Some("1111111111111111111111111111111111111111")
+        );
+        assert_eq!(
+            refs.resolve("v1.0").as_deref(),
+            Some("3333333333333333333333333333333333333333")
+        );
+        assert_eq!(refs.resolve("4a97465").as_deref(), Some("4a97465"));
+        assert_eq!(refs.resolve("develop"), None);
+    }
+
+    #[test]
+    fn test_newest_tag() {
+        let refs = RemoteRefs::parse_ls_remote(
+            "\
+1111111111111111111111111111111111111111\trefs/heads/main
+2222222222222222222222222222222222222222\trefs/tags/v1.9
+3333333333333333333333333333333333333333\trefs/tags/v1.10
+4444444444444444444444444444444444444444\trefs/tags/v1.10^{}
+5555555555555555555555555555555555555555\trefs/tags/v2.0-rc1
+6666666666666666666666666666666666666666\trefs/tags/20.0
+",
+        );
+
+        assert_eq!(refs.newest_tag("v1.9"), Some("v1.10"));
+        assert_eq!(refs.newest_tag("v1.10"), Some("v1.10"));
+        assert_eq!(refs.newest_tag("main"), None);
+        assert_eq!(refs.newest_tag("v2.0-rc1"), None);
+    }
+
+    #[async_std::test]
+    async fn test_resolve_default_branch() {
+        let advertisement = [
+            pkt_line("# service=git-upload-pack\n"),
+            "0000".into(),
+            pkt_line(
+                "1111111111111111111111111111111111111111 HEAD\0symref=HEAD:refs/heads/main\n",
+            ),
+            pkt_line("1111111111111111111111111111111111111111 refs/heads/main\n"),
+            "0000".into(),
+        ]
+        .concat();
+
+        let server = test_server::serve(move |path| match path {
+            "/tpope/vim-surround.git/info/refs?service=git-upload-pack" => {
+                test_server::Response::ok(advertisement.clone())
+            }
+            _ => test_server::Response::not_found(),
+        });
+
+        let options = DownloadOptions::default();
+        let (git_ref, commit) = resolve(
+            &format!("{}/tpope/vim-surround.git", server),
+            None,
+            &options,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(git_ref, "main");
+        assert_eq!(commit, "1111111111111111111111111111111111111111");
+
+        let error = resolve(&format!("{}/tpope/vim-missing.git", server), None, &options)
+            .await
+            .unwrap_err();
+
+        assert!(error.to_string().contains("404"));
+    }
+}
@@ -0,0 +1,138 @@
+//! What happened to each plugin during a run, for summing up at the end of it.
+
+use crate::State;
+use anyhow::Error;
+use serde::Serialize;
+use std::time::Duration;
+
+pub enum Outcome {
+    Installed,
+    UpToDate,
+    Failed(Error),
+    /// Installed, but thrown away along with the rest of the staging directory because another
+    /// plugin failed.
+    RolledBack,
+}
+
+pub struct PluginReport {
+    pub id: String,
+    pub outcome: Outcome,
+    /// How long the plugin took, not counting any time spent waiting for its turn to download.
+    pub duration: Duration,
+}
+
+/// The result of installing a set of plugins. `state` records every plugin that was installed, as
+/// well as the previous version of any that failed to update, so that it can still be removed.
+pub struct Report {
+    pub state: State,
+    pub plugins: Vec<PluginReport>,
+    /// Whether what was installed has been thrown away, leaving the pack directory as it was.
+    pub discarded: bool,
+}
+
+/// A plugin’s entry in the JSON output. Everything but the ID and status is null for plugins that
+/// failed to install.
+#[derive(Serialize)]
+pub struct PluginResult<'a> {
+    pub id: &'a str,
+    pub status: &'static str,
+    pub source: Option<&'a str>,
+    #[serde(rename = "ref")]
+    pub git_ref: Option<&'a str>,
+    pub commit: Option<&'a str>,
+    pub duration_ms: u64,
+    pub error: Option<String>,
+}
+
+impl Report {
+    pub fn failed(&self) -> usize {
+        self.plugins
+            .iter()
+            .filter(|plugin| matches!(plugin.outcome, Outcome::Failed(_)))
+            .count()
+    }
+
+    /// Records that everything installed was thrown away, so that no plugin is reported as
+    /// installed when it is not.
+    pub fn discard(&mut self) {
+        for plugin in &mut self.plugins {
+            if let Outcome::Installed = plugin.outcome {
+                plugin.outcome = Outcome::RolledBack;
+            }
+        }
+
+        self.discarded = true;
+    }
+
+    /// Prints a table of every plugin and what happened to it. Failures whose cause does not fit
+    /// on one line, such as a build command’s output, are printed in full after it.
+    pub fn print_summary(&self) {
+        let width = self.plugins.iter().map(|plugin| plugin.id.len()).max();
+        let width = width.unwrap_or_default();
+
+        eprintln!();
+
+        for PluginReport { id, outcome, .. } in &self.plugins {
+            match outcome {
+                Outcome::Installed => eprintln!("{:width$}  installed", id, width = width),
+                Outcome::UpToDate => eprintln!("{:width$}  up to date", id, width = width),
+                Outcome::RolledBack => eprintln!("{:width$}  rolled back", id, width = width),
+                Outcome::Failed(e) => {
+                    let cause = format!("{:#}", e);
+                    let first_line = cause.lines().next().unwrap_or_default();
+                    eprintln!("{:width$}  failed: {}", id, first_line, width = width);
+                }
+            }
+        }
+
+        for PluginReport { id, outcome, .. } in &self.plugins {
+            if let Outcome::Failed(e) = outcome {
+                let cause = format!("{:#}", e);
+
+                if cause.contains('\n') {
+                    eprintln!("\n{}:\n{}", id, cause);
+                }
+            }
+        }
+
+        eprintln!(
+            "\n{} of {} plugins failed to install{}",
+            self.failed(),
+            self.plugins.len(),
+            if self.discarded {
+                ", so nothing was changed"
+            } else {
+                ""
+            }
+        );
+    }
+
+    pub fn results(&self) -> Vec<PluginResult<'_>> {
+        self.plugins
+            .iter()
+            .map(|plugin| {
+                let (status, error) = match &plugin.outcome {
+                    Outcome::Installed => ("installed", None),
+                    Outcome::UpToDate => ("up_to_date", None),
+                    Outcome::RolledBack => ("rolled_back", None),
+                    Outcome::Failed(e) => ("failed", Some(format!("{:#}", e))),
+                };
+
+                let installed = match error {
+                    Some(_) => None,
+                    None => self.state.plugins.get(&plugin.id),
+                };
+
+                PluginResult {
+                    id: &plugin.id,
+                    status,
+                    source: installed.map(|installed| installed.source.as_str()),
+                    git_ref: installed.and_then(|installed| installed.git_ref.as_deref()),
+                    commit: installed.and_then(|installed| installed.version.commit.as_deref()),
+                    duration_ms: plugin.duration.as_millis() as u64,
+                    error,
+                }
+            })
+            .collect()
+    }
+}
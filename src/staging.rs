@@ -0,0 +1,188 @@
+//! Changes to the pack directory are made to a copy of it, which only replaces the real one once
+//! everything has been installed. That way a failure part of the way through never leaves Vim with
+//! half of its plugins.
+
+use crate::{generations, remove_path};
+use anyhow::{anyhow, Context, Result};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+pub struct Staging {
+    pack_dir: PathBuf,
+    path: PathBuf,
+}
+
+// Siblings of the pack directory are hidden so that Vim does not mistake them for packages.
+pub(crate) fn sibling(pack_dir: &Path, suffix: &str) -> Result<PathBuf> {
+    let parent = pack_dir.parent();
+    let name = pack_dir.file_name();
+
+    match (parent, name) {
+        (Some(parent), Some(name)) => {
+            Ok(parent.join(format!(".{}.{}", name.to_string_lossy(), suffix)))
+        }
+        _ => Err(anyhow!(
+            "pack_dir {} has no parent directory",
+            pack_dir.display()
+        )),
+    }
+}
+
+// Files are hard-linked rather than copied, which is cheap no matter how large the plugins are.
+// This is safe because strand never modifies a file in place: existing plugins are only ever
+// removed, and everything it writes is either a new file or replaces an old one by renaming. The
+// pack directory that is replaced gets copies of its own when it is kept as a generation.
+fn link_tree(from: &Path, to: &Path) -> io::Result<()> {
+    fs::create_dir(to)?;
+
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        let to = to.join(entry.file_name());
+
+        if file_type.is_dir() {
+            link_tree(&entry.path(), &to)?;
+        } else if file_type.is_symlink() {
+            copy_symlink(&entry.path(), &to)?;
+        } else if fs::hard_link(entry.path(), &to).is_err() {
+            fs::copy(entry.path(), &to)?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(unix)]
+fn copy_symlink(from: &Path, to: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(fs::read_link(from)?, to)
+}
+
+#[cfg(not(unix))]
+fn copy_symlink(from: &Path, to: &Path) -> io::Result<()> {
+    fs::copy(from, to).map(|_| ())
+}
+
+impl Staging {
+    /// Sets up a staging directory for the given pack directory, starting out with the pack
+    /// directory’s contents if `keep` is set and empty otherwise. Anything left behind by a run
+    /// that was interrupted is cleared out first.
+    pub async fn new(pack_dir: &Path, keep: bool) -> Result<Self> {
+        let path = sibling(pack_dir, "staging")?;
+
+        if path.exists() {
+            remove_path(&path).await?;
+        }
+
+        if keep && pack_dir.exists() {
+            link_tree(pack_dir, &path)
+                .with_context(|| format!("failed to copy {}", pack_dir.display()))?;
+        } else {
+            fs::create_dir_all(&path)?;
+        }
+
+        Ok(Self {
+            pack_dir: pack_dir.into(),
+            path,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Replaces the pack directory with the staging directory. The old pack directory is moved
+    /// aside rather than deleted until the new one is in place, so that it can be put back if that
+    /// fails, and is then kept as one of the last `generations` generations.
+    pub async fn commit(self, generations: usize) -> Result<()> {
+        let backup = sibling(&self.pack_dir, "old")?;
+
+        generations::stamp_new(&self.pack_dir, &self.path)?;
+
+        if backup.exists() {
+            remove_path(&backup).await?;
+        }
+
+        let had_pack_dir = self.pack_dir.exists();
+
+        if had_pack_dir {
+            fs::rename(&self.pack_dir, &backup)?;
+        }
+
+        if let Err(e) = fs::rename(&self.path, &self.pack_dir) {
+            if had_pack_dir {
+                fs::rename(&backup, &self.pack_dir)?;
+            }
+
+            return Err(e).with_context(|| {
+                format!(
+                    "failed to move new plugins into {}",
+                    self.pack_dir.display()
+                )
+            });
+        }
+
+        if had_pack_dir {
+            if let Err(e) = generations::retire(&self.pack_dir, &backup, generations).await {
+                eprintln!(
+                    "Warning: failed to put away {} -- {:#}",
+                    backup.display(),
+                    e
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Throws away the staging directory, leaving the pack directory as it was.
+    pub async fn discard(self) -> Result<()> {
+        remove_path(&self.path).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[async_std::test]
+    async fn test_staging() {
+        let dir = std::env::temp_dir().join("strand-test-staging");
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let pack_dir = dir.join("strand");
+        fs::create_dir_all(pack_dir.join("start/vim-surround")).unwrap();
+        fs::write(pack_dir.join("start/vim-surround/surround.vim"), "old").unwrap();
+
+        // Discarded changes never reach the pack directory…
+        let staging = Staging::new(&pack_dir, true).await.unwrap();
+        assert_eq!(staging.path(), dir.join(".strand.staging"));
+        remove_path(&staging.path().join("start/vim-surround"))
+            .await
+            .unwrap();
+        staging.discard().await.unwrap();
+        assert!(pack_dir.join("start/vim-surround/surround.vim").is_file());
+
+        // …while committed ones replace it entirely.
+        let staging = Staging::new(&pack_dir, true).await.unwrap();
+        let file = staging.path().join("start/vim-surround/surround.vim");
+        fs::remove_file(&file).unwrap();
+        fs::write(&file, "new").unwrap();
+        fs::create_dir_all(staging.path().join("opt/vim-repeat")).unwrap();
+        staging.commit(0).await.unwrap();
+        assert_eq!(
+            fs::read_to_string(pack_dir.join("start/vim-surround/surround.vim")).unwrap(),
+            "new"
+        );
+        assert!(pack_dir.join("opt/vim-repeat").is_dir());
+
+        let entries: Vec<_> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(entries, ["strand"]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,132 @@
+use crate::{remove_path, Format, LockedPlugin, Lockfile};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+// The state file lives inside the pack directory so that the two can never get out of sync by e.g.
+// the user deleting the directory. Vim only looks for plugins in its `start` and `opt` directories,
+// so it is ignored.
+const STATE_FILE: &str = ".strand-state.yaml";
+
+/// A plugin as it is currently installed in the pack directory.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct InstalledPlugin {
+    /// The URL the plugin was downloaded from.
+    pub source: String,
+    /// The Git reference the plugin was installed from if it came from a Git repo, which is the
+    /// repo’s default branch when the config file does not specify one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub git_ref: Option<String>,
+    #[serde(flatten)]
+    pub version: LockedPlugin,
+    /// The directory the plugin was installed into, relative to the pack directory.
+    pub dir: PathBuf,
+    /// The command the plugin was built with, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub run: Option<String>,
+    /// The archive format the config file gave for the plugin, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub format: Option<Format>,
+    /// When the plugin was installed, in seconds since the Unix epoch. Plugins installed by older
+    /// versions of strand have no record of this.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub installed_at: Option<u64>,
+}
+
+impl InstalledPlugin {
+    /// Checks that the plugin has not been removed from disk behind our back.
+    pub fn is_present(&self, pack_dir: &Path) -> bool {
+        pack_dir.join(&self.dir).exists()
+    }
+
+    pub async fn remove(&self, pack_dir: &Path) -> Result<()> {
+        let path = pack_dir.join(&self.dir);
+
+        if path.exists() {
+            remove_path(&path).await?;
+        }
+
+        Ok(())
+    }
+}
+
+/// strand’s record of what is installed in the pack directory, keyed by plugin ID.
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct State {
+    pub plugins: BTreeMap<String, InstalledPlugin>,
+}
+
+impl State {
+    /// Reads the state file from the given pack directory, returning `None` if strand has not
+    /// recorded anything there yet.
+    pub async fn read(pack_dir: &Path) -> Result<Option<Self>> {
+        use async_std::fs;
+
+        let path = pack_dir.join(STATE_FILE);
+
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let state = fs::read_to_string(&path).await?;
+        let state = yaml::from_str(&state).with_context(|| {
+            format!(
+                "failed to parse state file at {} -- run strand with --fresh to reinstall everything",
+                path.display()
+            )
+        })?;
+
+        Ok(Some(state))
+    }
+
+    pub async fn write(&self, pack_dir: &Path) -> Result<()> {
+        use async_std::fs;
+
+        // The state file is replaced rather than overwritten, since it may be hard-linked into the
+        // pack directory that is currently in use.
+        let path = pack_dir.join(STATE_FILE);
+        let tmp_path = path.with_extension("tmp");
+        fs::write(&tmp_path, yaml::to_string(self)?).await?;
+        fs::rename(&tmp_path, &path).await?;
+
+        Ok(())
+    }
+
+    /// Deletes every plugin that is recorded here but whose ID is not in `keep` from the pack
+    /// directory, returning the IDs of the ones it deleted.
+    pub async fn remove_dropped(
+        &mut self,
+        keep: &[String],
+        pack_dir: &Path,
+    ) -> Result<Vec<String>> {
+        let dropped: Vec<_> = self
+            .plugins
+            .keys()
+            .filter(|id| !keep.contains(id))
+            .cloned()
+            .collect();
+
+        for id in &dropped {
+            if let Some(plugin) = self.plugins.remove(id) {
+                plugin.remove(pack_dir).await?;
+            }
+        }
+
+        Ok(dropped)
+    }
+}
+
+impl From<&State> for Lockfile {
+    fn from(state: &State) -> Self {
+        let plugins = state
+            .plugins
+            .iter()
+            .map(|(id, plugin)| (id.clone(), plugin.version.clone()))
+            .collect();
+
+        Self { plugins }
+    }
+}
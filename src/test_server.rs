@@ -0,0 +1,77 @@
+//! A minimal HTTP server that tests can use as a stand-in for Git hosts.
+
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::TcpListener,
+    thread,
+};
+
+pub struct Response {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    pub fn ok(body: impl Into<Vec<u8>>) -> Self {
+        Self {
+            status: 200,
+            headers: Vec::new(),
+            body: body.into(),
+        }
+    }
+
+    pub fn not_found() -> Self {
+        Self {
+            status: 404,
+            headers: Vec::new(),
+            body: b"Not Found".to_vec(),
+        }
+    }
+}
+
+/// Serves requests on a background thread for the lifetime of the test process, passing the
+/// path and query of each one to the handler.
+pub fn serve(handler: impl Fn(&str) -> Response + Send + 'static) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = match stream {
+                Ok(stream) => stream,
+                Err(_) => continue,
+            };
+
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut request_line = String::new();
+            if reader.read_line(&mut request_line).is_err() {
+                continue;
+            }
+
+            // Skip past the headers, since none of the tests care about them.
+            let mut line = String::new();
+            while reader.read_line(&mut line).is_ok_and(|n| n > 2) {
+                line.clear();
+            }
+
+            let path = request_line.split(' ').nth(1).unwrap_or("/");
+            let response = handler(path);
+
+            let mut head = format!(
+                "HTTP/1.1 {} Test\r\nContent-Length: {}\r\nConnection: close\r\n",
+                response.status,
+                response.body.len()
+            );
+            for (name, value) in &response.headers {
+                head.push_str(&format!("{}: {}\r\n", name, value));
+            }
+            head.push_str("\r\n");
+
+            let _ = stream.write_all(head.as_bytes());
+            let _ = stream.write_all(&response.body);
+        }
+    });
+
+    format!("http://{}", addr)
+}